@@ -0,0 +1,384 @@
+use crate::clock::now;
+use failure::Error;
+use rocket::http::RawStr;
+use rocket::request::FromFormValue;
+use rusqlite::{params, Connection};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How often expired events are purged.
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS events (
+        id INTEGER PRIMARY KEY,
+        timestamp INTEGER NOT NULL,
+        kind TEXT NOT NULL,
+        app TEXT NOT NULL,
+        platform TEXT NOT NULL,
+        arch TEXT,
+        from_version TEXT,
+        to_version TEXT,
+        channel TEXT,
+        client_id TEXT
+    );
+    CREATE INDEX IF NOT EXISTS events_timestamp ON events (timestamp);
+";
+
+/// The kind of an analytics event.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EventKind {
+    UpdateCheck,
+    Download,
+}
+
+impl ToString for EventKind {
+    fn to_string(&self) -> String {
+        match &self {
+            EventKind::UpdateCheck => "update_check".to_string(),
+            EventKind::Download => "download".to_string(),
+        }
+    }
+}
+
+/// An update check or download as recorded in the analytics store.
+#[derive(Debug)]
+pub struct Event {
+    pub kind: EventKind,
+    pub app: String,
+    pub platform: String,
+
+    /// The architecture of the served release, `None` when no update was offered or the
+    /// release is not specific to one.
+    pub arch: Option<String>,
+    pub from_version: Option<String>,
+    pub to_version: Option<String>,
+    pub channel: Option<String>,
+
+    /// The identifier of the client, it is anonymised before it is stored.
+    pub client_id: Option<String>,
+}
+
+/// The period events are grouped by in reports.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Interval {
+    Day,
+    Week,
+    Month,
+}
+
+impl Interval {
+    /// Returns the sqlite `strftime` format of a period.
+    fn format(self) -> &'static str {
+        match self {
+            Interval::Day => "%Y-%m-%d",
+            Interval::Week => "%Y-W%W",
+            Interval::Month => "%Y-%m",
+        }
+    }
+}
+
+impl<'v> FromFormValue<'v> for Interval {
+    type Error = &'v RawStr;
+
+    fn from_form_value(value: &'v RawStr) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "day" => Ok(Interval::Day),
+            "week" => Ok(Interval::Week),
+            "month" => Ok(Interval::Month),
+            _ => Err(value),
+        }
+    }
+}
+
+/// The number of clients per version that checked for updates in a period.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct VersionCount {
+    pub period: String,
+    pub version: String,
+    pub clients: u32,
+    pub checks: u32,
+}
+
+/// The number of downloads per version and platform in a period.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct DownloadCount {
+    pub period: String,
+    pub version: String,
+    pub platform: String,
+    pub downloads: u32,
+}
+
+/// Analytics stores update checks and downloads in an embedded sqlite database.
+pub struct Analytics {
+    /// `None` when analytics are disabled.
+    conn: Option<Mutex<Connection>>,
+    retention: Option<Duration>,
+    salt: String,
+    last_purge: Mutex<Instant>,
+}
+
+impl Analytics {
+    /// Opens or creates the database at the given path. Events older than the retention period
+    /// are purged, they are kept forever when there is none. A salt is required, client ids
+    /// hashed without one can be recovered by hashing guessed ids.
+    pub fn open(path: &str, retention: Option<Duration>, salt: &str) -> Result<Self, Error> {
+        if salt.trim().is_empty() {
+            bail!("A salt is required to anonymise client ids");
+        }
+
+        let conn = Connection::open(path)?;
+        conn.execute_batch(SCHEMA)?;
+
+        let analytics = Analytics {
+            conn: Some(Mutex::new(conn)),
+            retention,
+            salt: salt.to_string(),
+            last_purge: Mutex::new(Instant::now()),
+        };
+        analytics.purge()?;
+
+        Ok(analytics)
+    }
+
+    /// Returns an analytics store that does not record anything.
+    pub fn disabled() -> Self {
+        Analytics {
+            conn: None,
+            retention: None,
+            salt: String::new(),
+            last_purge: Mutex::new(Instant::now()),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.conn.is_some()
+    }
+
+    /// Records an event, expired events are purged periodically.
+    pub fn record(&self, event: &Event) -> Result<(), Error> {
+        let conn = match &self.conn {
+            Some(conn) => conn,
+            None => return Ok(()),
+        };
+
+        conn.lock().unwrap().execute(
+            "INSERT INTO events
+                (timestamp, kind, app, platform, arch, from_version, to_version, channel, client_id)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            params![
+                now(),
+                event.kind.to_string(),
+                event.app,
+                event.platform,
+                event.arch,
+                event.from_version,
+                event.to_version,
+                event.channel,
+                event.client_id.as_ref().map(|x| self.anonymise(x)),
+            ],
+        )?;
+
+        let mut last_purge = self.last_purge.lock().unwrap();
+        if last_purge.elapsed() >= PURGE_INTERVAL {
+            *last_purge = Instant::now();
+            drop(last_purge);
+            self.purge()?;
+        }
+
+        Ok(())
+    }
+
+    /// Deletes all events older than the retention period, returns the number of deleted events.
+    pub fn purge(&self) -> Result<usize, Error> {
+        match (&self.conn, self.retention) {
+            (Some(conn), Some(retention)) => Ok(conn.lock().unwrap().execute(
+                "DELETE FROM events WHERE timestamp < ?1",
+                params![now() - retention.as_secs() as i64],
+            )?),
+            _ => Ok(0),
+        }
+    }
+
+    /// Returns the distribution of client versions over time, based on update checks between
+    /// the `since` and `until` unix timestamps.
+    pub fn version_distribution(
+        &self,
+        since: i64,
+        until: i64,
+        interval: Interval,
+    ) -> Result<Vec<VersionCount>, Error> {
+        let conn = match &self.conn {
+            Some(conn) => conn.lock().unwrap(),
+            None => return Ok(vec![]),
+        };
+
+        let mut stmt = conn.prepare(
+            "SELECT strftime(?1, timestamp, 'unixepoch') AS period, from_version,
+                    COUNT(DISTINCT COALESCE(client_id, id)), COUNT(*)
+             FROM events
+             WHERE kind = ?2 AND from_version IS NOT NULL AND timestamp >= ?3 AND timestamp < ?4
+             GROUP BY period, from_version
+             ORDER BY period, from_version",
+        )?;
+        let rows = stmt.query_map(
+            params![
+                interval.format(),
+                EventKind::UpdateCheck.to_string(),
+                since,
+                until
+            ],
+            |row| {
+                Ok(VersionCount {
+                    period: row.get(0)?,
+                    version: row.get(1)?,
+                    clients: row.get(2)?,
+                    checks: row.get(3)?,
+                })
+            },
+        )?;
+
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Returns the number of downloads per version and platform over time.
+    pub fn downloads(
+        &self,
+        since: i64,
+        until: i64,
+        interval: Interval,
+    ) -> Result<Vec<DownloadCount>, Error> {
+        let conn = match &self.conn {
+            Some(conn) => conn.lock().unwrap(),
+            None => return Ok(vec![]),
+        };
+
+        let mut stmt = conn.prepare(
+            "SELECT strftime(?1, timestamp, 'unixepoch') AS period, to_version, platform, COUNT(*)
+             FROM events
+             WHERE kind = ?2 AND to_version IS NOT NULL AND timestamp >= ?3 AND timestamp < ?4
+             GROUP BY period, to_version, platform
+             ORDER BY period, to_version, platform",
+        )?;
+        let rows = stmt.query_map(
+            params![
+                interval.format(),
+                EventKind::Download.to_string(),
+                since,
+                until
+            ],
+            |row| {
+                Ok(DownloadCount {
+                    period: row.get(0)?,
+                    version: row.get(1)?,
+                    platform: row.get(2)?,
+                    downloads: row.get(3)?,
+                })
+            },
+        )?;
+
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Hashes a client identifier with the configured salt, so it cannot be traced back.
+    fn anonymise(&self, client_id: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.salt.as_bytes());
+        hasher.update(b":");
+        hasher.update(client_id.as_bytes());
+
+        hasher.finalize().iter().fold(String::new(), |mut out, x| {
+            let _ = write!(out, "{:02x}", x);
+            out
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn event(kind: EventKind, from: &str, to: &str, client_id: &str) -> Event {
+        Event {
+            kind,
+            app: "tacitic/app".to_string(),
+            platform: "osx".to_string(),
+            arch: None,
+            from_version: Some(from.to_string()),
+            to_version: Some(to.to_string()),
+            channel: None,
+            client_id: Some(client_id.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_reports() {
+        let analytics = Analytics::open(":memory:", None, "salt").unwrap();
+        analytics
+            .record(&event(EventKind::UpdateCheck, "1.2.0", "1.3.0", "a"))
+            .unwrap();
+        analytics
+            .record(&event(EventKind::UpdateCheck, "1.2.0", "1.3.0", "a"))
+            .unwrap();
+        analytics
+            .record(&event(EventKind::UpdateCheck, "1.2.0", "1.3.0", "b"))
+            .unwrap();
+        analytics
+            .record(&event(EventKind::Download, "1.2.0", "1.3.0", "b"))
+            .unwrap();
+
+        let versions = analytics
+            .version_distribution(0, now() + 1, Interval::Month)
+            .unwrap();
+        assert_eq!(versions.len(), 1);
+        assert_eq!(versions[0].version, "1.2.0");
+        assert_eq!(versions[0].clients, 2);
+        assert_eq!(versions[0].checks, 3);
+
+        let downloads = analytics.downloads(0, now() + 1, Interval::Day).unwrap();
+        assert_eq!(downloads.len(), 1);
+        assert_eq!(downloads[0].version, "1.3.0");
+        assert_eq!(downloads[0].downloads, 1);
+
+        assert!(analytics
+            .downloads(now() + 1, now() + 2, Interval::Day)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_anonymise() {
+        let analytics = Analytics::open(":memory:", None, "salt").unwrap();
+        let id = analytics.anonymise("client-a");
+        assert_eq!(id, analytics.anonymise("client-a"));
+        assert_ne!(id, analytics.anonymise("client-b"));
+        assert!(!id.contains("client-a"));
+    }
+
+    #[test]
+    fn test_open_without_salt() {
+        assert!(Analytics::open(":memory:", None, "").is_err());
+        assert!(Analytics::open(":memory:", None, " ").is_err());
+    }
+
+    #[test]
+    fn test_purge() {
+        let analytics = Analytics::open(":memory:", Some(Duration::from_secs(60)), "salt").unwrap();
+        analytics
+            .record(&event(EventKind::Download, "1.2.0", "1.3.0", "a"))
+            .unwrap();
+        analytics
+            .conn
+            .as_ref()
+            .unwrap()
+            .lock()
+            .unwrap()
+            .execute("UPDATE events SET timestamp = timestamp - 120", params![])
+            .unwrap();
+
+        assert_eq!(analytics.purge().unwrap(), 1);
+    }
+}
@@ -0,0 +1,366 @@
+use crate::clock;
+use failure::Error;
+use rocket::FromForm;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// The actor of actions nuts performs by itself, e.g. delivering webhooks.
+pub const SYSTEM_ACTOR: &str = "nuts";
+
+/// The size in bytes at which the audit log is rotated, the previous file is kept with a '.1'
+/// suffix.
+pub const DEFAULT_MAX_SIZE: u64 = 10_000_000;
+
+/// Failed authentications are recorded at most this many times per client address per window,
+/// the others are counted and recorded as one 'auth.suppressed' entry after the window.
+const FAILURE_LIMIT: u32 = 10;
+
+/// The length of the window for failed authentications, in seconds.
+const FAILURE_WINDOW: i64 = 60;
+
+/// Client addresses tracked per window, failures of further addresses are counted together.
+const MAX_TRACKED_ADDRESSES: usize = 10_000;
+
+/// A record of an administrative or security-relevant event.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    /// Unix timestamp of the event.
+    pub timestamp: i64,
+
+    /// What happened, e.g. 'policy.yank', 'auth.failed' or 'cache.purge'.
+    pub action: String,
+
+    /// The name of the token that performed the action, 'nuts' for actions of nuts itself.
+    pub actor: Option<String>,
+
+    /// The address of the client.
+    pub ip: Option<String>,
+
+    /// What the action applied to, e.g. a version or a path.
+    pub target: Option<String>,
+
+    pub detail: Option<String>,
+}
+
+impl Entry {
+    pub fn new(action: &str) -> Self {
+        Entry {
+            timestamp: clock::now(),
+            action: action.to_string(),
+            actor: None,
+            ip: None,
+            target: None,
+            detail: None,
+        }
+    }
+
+    pub fn actor(mut self, actor: Option<&str>) -> Self {
+        self.actor = actor.map(str::to_string);
+        self
+    }
+
+    pub fn ip(mut self, ip: Option<String>) -> Self {
+        self.ip = ip;
+        self
+    }
+
+    pub fn target(mut self, target: &str) -> Self {
+        self.target = Some(target.to_string());
+        self
+    }
+
+    pub fn detail(mut self, detail: &str) -> Self {
+        self.detail = Some(detail.to_string());
+        self
+    }
+}
+
+/// The query parameters accepted by the audit endpoint, unset fields match any entry.
+#[derive(Debug, Default, FromForm)]
+pub struct Query {
+    pub since: Option<i64>,
+    pub until: Option<i64>,
+    pub actor: Option<String>,
+    pub action: Option<String>,
+    pub offset: Option<usize>,
+    pub limit: Option<usize>,
+}
+
+impl Query {
+    pub fn matches(&self, entry: &Entry) -> bool {
+        self.since.map_or(true, |x| entry.timestamp >= x)
+            && self.until.map_or(true, |x| entry.timestamp < x)
+            && self
+                .actor
+                .as_ref()
+                .map_or(true, |x| entry.actor.as_ref() == Some(x))
+            && self
+                .action
+                .as_ref()
+                .map_or(true, |x| entry.action.starts_with(x.as_str()))
+    }
+}
+
+/// An append-only audit log stored as JSON lines. Failing to write an entry is logged, it does
+/// not fail the request that caused it. Clones write to the same file.
+#[derive(Debug, Clone, Default)]
+pub struct AuditLog {
+    path: Option<PathBuf>,
+    max_size: u64,
+    file: Arc<Mutex<Option<Writer>>>,
+    failures: Arc<Mutex<Failures>>,
+}
+
+#[derive(Debug)]
+struct Writer {
+    file: File,
+    size: u64,
+}
+
+/// The failed authentications per client address in the current window.
+#[derive(Debug, Default)]
+struct Failures {
+    window_start: i64,
+    counts: HashMap<Option<String>, u32>,
+}
+
+impl AuditLog {
+    pub fn open(path: PathBuf, max_size: u64) -> Result<Self, Error> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+
+        Ok(AuditLog {
+            path: Some(path),
+            max_size,
+            file: Arc::new(Mutex::new(Some(Writer { file, size }))),
+            failures: Arc::default(),
+        })
+    }
+
+    /// An audit log that records nothing.
+    pub fn disabled() -> Self {
+        AuditLog::default()
+    }
+
+    pub fn record(&self, entry: Entry) {
+        let mut writer = self.file.lock().unwrap();
+        let writer = match writer.as_mut() {
+            Some(writer) => writer,
+            None => return,
+        };
+
+        let mut line = serde_json::to_string(&entry).unwrap();
+        line.push('\n');
+        if writer.size > 0 && writer.size + line.len() as u64 > self.max_size {
+            if let Err(e) = self.rotate(writer) {
+                error!(error:% = e; "could not rotate audit log");
+            }
+        }
+
+        // A single write, so concurrent entries are not interleaved.
+        match writer.file.write_all(line.as_bytes()) {
+            Ok(_) => writer.size += line.len() as u64,
+            Err(e) => error!(action = entry.action, error:% = e; "could not write audit log"),
+        }
+    }
+
+    /// Records a failed authentication, unless the client address already failed too often in
+    /// the current window.
+    pub fn record_failure(&self, entry: Entry) {
+        let (suppressed, record) = {
+            let mut failures = self.failures.lock().unwrap();
+            let mut suppressed = vec![];
+            if entry.timestamp - failures.window_start >= FAILURE_WINDOW {
+                suppressed = failures
+                    .counts
+                    .drain()
+                    .filter(|(_, count)| *count > FAILURE_LIMIT)
+                    .collect();
+                failures.window_start = entry.timestamp;
+            }
+
+            let mut key = entry.ip.clone();
+            if !failures.counts.contains_key(&key) && failures.counts.len() >= MAX_TRACKED_ADDRESSES
+            {
+                key = None;
+            }
+            let count = failures.counts.entry(key).or_insert(0);
+            *count += 1;
+            (suppressed, *count <= FAILURE_LIMIT)
+        };
+
+        for (ip, count) in suppressed {
+            self.record(
+                Entry::new("auth.suppressed")
+                    .ip(ip)
+                    .detail(&format!("{} failed authentications", count - FAILURE_LIMIT)),
+            );
+        }
+        if record {
+            self.record(entry);
+        }
+    }
+
+    /// Moves the log to the file with the '.1' suffix, replacing the previous one, and starts a
+    /// new file.
+    fn rotate(&self, writer: &mut Writer) -> Result<(), Error> {
+        let path = self.path.as_ref().unwrap();
+        fs::rename(path, rotated(path))?;
+        writer.file = OpenOptions::new().create(true).append(true).open(path)?;
+        writer.size = 0;
+        Ok(())
+    }
+
+    /// Returns the entries matching a query, newest first.
+    pub fn query(&self, query: &Query) -> Result<Vec<Entry>, Error> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(vec![]),
+        };
+
+        let mut out = vec![];
+        for path in &[rotated(path), path.clone()] {
+            let file = match fs::File::open(path) {
+                Ok(file) => file,
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+
+            for line in BufReader::new(file).lines() {
+                let line = line?;
+                // Skips a line that was cut off by a crash.
+                let entry: Entry = match serde_json::from_str(&line) {
+                    Ok(entry) => entry,
+                    Err(_) => continue,
+                };
+                if query.matches(&entry) {
+                    out.push(entry);
+                }
+            }
+        }
+        out.reverse();
+
+        Ok(out)
+    }
+}
+
+/// Returns the path of the rotated audit log.
+fn rotated(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".1");
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn entry(timestamp: i64, action: &str, actor: &str) -> Entry {
+        Entry {
+            timestamp,
+            ..Entry::new(action).actor(Some(actor))
+        }
+    }
+
+    #[test]
+    fn test_record_and_query() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.jsonl");
+
+        let log = AuditLog::open(path.clone(), DEFAULT_MAX_SIZE).unwrap();
+        log.record(entry(100, "policy.yank", "ops").target("1.2.0"));
+        log.record(entry(200, "auth.failed", "ci"));
+        log.record(entry(300, "policy.promote", "ops").detail("stable"));
+
+        // Entries survive a restart and are appended to.
+        let log = AuditLog::open(path, DEFAULT_MAX_SIZE).unwrap();
+        log.record(entry(400, "cache.purge", "ops"));
+
+        let all = log.query(&Query::default()).unwrap();
+        assert_eq!(all.len(), 4);
+        assert_eq!(all[0].action, "cache.purge");
+        assert_eq!(all[3].target.as_deref(), Some("1.2.0"));
+
+        let query = Query {
+            since: Some(100),
+            until: Some(400),
+            actor: Some("ops".to_string()),
+            ..Query::default()
+        };
+        let out = log.query(&query).unwrap();
+        assert_eq!(
+            out.iter().map(|x| x.timestamp).collect::<Vec<_>>(),
+            vec![300, 100]
+        );
+
+        let query = Query {
+            action: Some("policy.".to_string()),
+            ..Query::default()
+        };
+        assert_eq!(log.query(&query).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_disabled() {
+        let log = AuditLog::disabled();
+        log.record(Entry::new("cache.purge"));
+        assert!(log.query(&Query::default()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_rotate() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        let size = serde_json::to_string(&entry(100, "cache.purge", "ops"))
+            .unwrap()
+            .len() as u64
+            + 1;
+
+        let log = AuditLog::open(path.clone(), size * 2).unwrap();
+        for timestamp in 100..105 {
+            log.record(entry(timestamp, "cache.purge", "ops"));
+        }
+
+        // Only the current and the previous file are kept.
+        assert_eq!(fs::metadata(&path).unwrap().len(), size);
+        assert_eq!(fs::metadata(rotated(&path)).unwrap().len(), size * 2);
+        let out = log.query(&Query::default()).unwrap();
+        assert_eq!(
+            out.iter().map(|x| x.timestamp).collect::<Vec<_>>(),
+            vec![104, 103, 102]
+        );
+    }
+
+    #[test]
+    fn test_record_failure() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = AuditLog::open(dir.path().join("audit.jsonl"), DEFAULT_MAX_SIZE).unwrap();
+        let failure = |timestamp, ip: &str| Entry {
+            timestamp,
+            ..Entry::new("auth.failed").ip(Some(ip.to_string()))
+        };
+
+        for _ in 0..25 {
+            log.record_failure(failure(1000, "10.0.0.1"));
+        }
+        log.record_failure(failure(1000, "10.0.0.2"));
+        let query = Query {
+            action: Some("auth.failed".to_string()),
+            ..Query::default()
+        };
+        assert_eq!(log.query(&query).unwrap().len(), FAILURE_LIMIT as usize + 1);
+
+        // The next window starts with a summary of the suppressed failures.
+        log.record_failure(failure(1000 + FAILURE_WINDOW, "10.0.0.1"));
+        let out = log.query(&Query::default()).unwrap();
+        assert_eq!(out[0].action, "auth.failed");
+        assert_eq!(out[1].action, "auth.suppressed");
+        assert_eq!(out[1].ip.as_deref(), Some("10.0.0.1"));
+        assert_eq!(out[1].detail.as_deref(), Some("15 failed authentications"));
+        assert_eq!(log.query(&query).unwrap().len(), FAILURE_LIMIT as usize + 2);
+    }
+}
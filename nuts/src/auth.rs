@@ -0,0 +1,285 @@
+use crate::STABLE_CHANNEL;
+use base64::Engine;
+use failure::Error;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::fmt::Write;
+use std::fs;
+
+/// Name of the token configured with 'NUTS_SECRET_TOKEN'.
+pub const DEFAULT_TOKEN_NAME: &str = "default";
+
+/// The endpoints a token grants access to.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    /// Update checks, release notes, the release catalogue, analytics and metrics.
+    Update,
+    /// Downloads of release assets, as an alternative to signed urls. Downloads are not
+    /// restricted when 'NUTS_URL_SIGNATURE_SECRET' is not set, so the scope is only checked
+    /// when it is.
+    Download,
+    /// The administrative endpoints, the audit log and the dashboard.
+    Admin,
+}
+
+/// An API token, only the sha256 hash of the secret is stored.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Token {
+    pub name: String,
+
+    /// Hex encoded sha256 hash of the secret, optionally prefixed with 'sha256:'.
+    hash: String,
+
+    pub scopes: Vec<Scope>,
+
+    /// The apps (Github repositories) the token is valid for, any app when empty.
+    #[serde(default)]
+    pub apps: Vec<String>,
+
+    /// The channels the token may request updates from, any channel when empty.
+    #[serde(default)]
+    pub channels: Vec<String>,
+
+    /// Unix timestamp after which the token is no longer valid.
+    #[serde(default)]
+    pub expires_at: Option<i64>,
+}
+
+impl Token {
+    /// Returns whether the token may request releases from a channel, the stable channel is
+    /// represented by `None`.
+    pub fn allows_channel(&self, channel: Option<&str>) -> bool {
+        let channel = channel.unwrap_or(STABLE_CHANNEL);
+        self.channels.is_empty() || self.channels.iter().any(|x| x == channel)
+    }
+
+    fn digest(&self) -> &str {
+        self.hash.trim_start_matches("sha256:")
+    }
+}
+
+/// The name of the token a request was authenticated with, kept in the request-local cache.
+#[derive(Debug, Default)]
+pub struct Identity(pub Option<String>);
+
+/// The reason a token was rejected.
+#[derive(Debug, Clone, PartialEq, Fail)]
+pub enum AuthError {
+    #[fail(display = "Unknown token")]
+    UnknownToken,
+    #[fail(display = "Token {} has expired", _0)]
+    Expired(String),
+    #[fail(display = "Token {} is not allowed to access this endpoint", _0)]
+    Forbidden(String),
+}
+
+/// The configured API tokens.
+#[derive(Debug, Default)]
+pub struct Tokens(Vec<Token>);
+
+impl Tokens {
+    /// Reads tokens from a JSON file that contains a list of tokens.
+    pub fn load(path: &str) -> Result<Self, Error> {
+        let mut tokens: Vec<Token> = serde_json::from_str(&fs::read_to_string(path)?)?;
+        for token in &mut tokens {
+            token.hash = token.digest().to_lowercase();
+        }
+
+        Ok(Tokens(tokens))
+    }
+
+    /// Adds a token with all scopes for a plain secret.
+    pub fn add_secret(&mut self, name: &str, secret: &str) {
+        self.0.push(Token {
+            name: name.to_string(),
+            hash: hash(secret),
+            scopes: vec![Scope::Update, Scope::Download, Scope::Admin],
+            apps: vec![],
+            channels: vec![],
+            expires_at: None,
+        });
+    }
+
+    /// Returns true when no tokens are configured, access is not restricted in that case.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Returns the token matching a secret, regardless of its scopes and expiry.
+    pub fn find(&self, secret: &str) -> Option<&Token> {
+        let digest = hash(secret);
+
+        // Compares against every token, so the time taken does not depend on which one matches.
+        self.0
+            .iter()
+            .filter(|x| constant_time_eq(x.digest().as_bytes(), digest.as_bytes()))
+            .last()
+    }
+
+    /// Returns the token matching a secret when it is valid for the scope and app at the given
+    /// unix timestamp.
+    pub fn authenticate(
+        &self,
+        secret: &str,
+        scope: Scope,
+        app: &str,
+        now: i64,
+    ) -> Result<&Token, AuthError> {
+        let token = self.find(secret).ok_or(AuthError::UnknownToken)?;
+
+        if token.expires_at.map_or(false, |x| x <= now) {
+            return Err(AuthError::Expired(token.name.clone()));
+        }
+
+        if !token.scopes.contains(&scope)
+            || !(token.apps.is_empty() || token.apps.iter().any(|x| x == app))
+        {
+            return Err(AuthError::Forbidden(token.name.clone()));
+        }
+
+        Ok(token)
+    }
+}
+
+/// Returns the hex encoded sha256 hash of a secret.
+pub fn hash(secret: &str) -> String {
+    Sha256::digest(secret.as_bytes())
+        .iter()
+        .fold(String::new(), |mut out, x| {
+            let _ = write!(out, "{:02x}", x);
+            out
+        })
+}
+
+/// Compares two byte strings in constant time for strings of equal length.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Returns the token of an 'Authorization: Bearer <token>' header.
+pub fn bearer_token(header: &str) -> Option<&str> {
+    header
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+        .filter(|x| !x.is_empty())
+}
+
+/// Returns the password of an 'Authorization: Basic <credentials>' header, browsers send the
+/// token as the password on the dashboard.
+pub fn basic_password(header: &str) -> Option<String> {
+    let credentials = base64::engine::general_purpose::STANDARD
+        .decode(header.strip_prefix("Basic ")?.trim())
+        .ok()?;
+    let credentials = String::from_utf8(credentials).ok()?;
+
+    credentials
+        .split_once(':')
+        .map(|(_, password)| password.to_string())
+        .filter(|x| !x.is_empty())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn tokens() -> Tokens {
+        let json = format!(
+            r#"[
+                {{"name": "ci", "hash": "sha256:{}", "scopes": ["update"], "channels": ["beta"]}},
+                {{"name": "ops", "hash": "{}", "scopes": ["admin"], "apps": ["tacitic/app"],
+                  "expires_at": 1000}}
+            ]"#,
+            hash("ci-secret"),
+            hash("ops-secret")
+        );
+
+        Tokens(serde_json::from_str(&json).unwrap())
+    }
+
+    #[test]
+    fn test_authenticate() {
+        let tokens = tokens();
+
+        let token = tokens
+            .authenticate("ci-secret", Scope::Update, "tacitic/app", 0)
+            .unwrap();
+        assert_eq!(token.name, "ci");
+        assert!(token.allows_channel(Some("beta")));
+        assert!(!token.allows_channel(None));
+
+        assert_eq!(
+            tokens
+                .authenticate("ci-secret", Scope::Admin, "tacitic/app", 0)
+                .unwrap_err(),
+            AuthError::Forbidden("ci".to_string())
+        );
+        assert_eq!(
+            tokens
+                .authenticate("ops-secret", Scope::Admin, "tacitic/other", 0)
+                .unwrap_err(),
+            AuthError::Forbidden("ops".to_string())
+        );
+        assert_eq!(
+            tokens
+                .authenticate("ops-secret", Scope::Admin, "tacitic/app", 1000)
+                .unwrap_err(),
+            AuthError::Expired("ops".to_string())
+        );
+        assert_eq!(
+            tokens
+                .authenticate("wrong", Scope::Update, "tacitic/app", 0)
+                .unwrap_err(),
+            AuthError::UnknownToken
+        );
+    }
+
+    #[test]
+    fn test_add_secret() {
+        let mut tokens = Tokens::default();
+        assert!(tokens.is_empty());
+
+        tokens.add_secret(DEFAULT_TOKEN_NAME, "secret");
+        for scope in &[Scope::Update, Scope::Download, Scope::Admin] {
+            assert!(tokens.authenticate("secret", *scope, "any/app", 0).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_bearer_token() {
+        assert_eq!(bearer_token("Bearer abc"), Some("abc"));
+        assert_eq!(bearer_token("Bearer"), None);
+        assert_eq!(bearer_token("Bearer "), None);
+        assert_eq!(bearer_token("Basic abc"), None);
+        assert_eq!(bearer_token(""), None);
+    }
+
+    #[test]
+    fn test_basic_password() {
+        // admin:secret
+        assert_eq!(
+            basic_password("Basic YWRtaW46c2VjcmV0").as_deref(),
+            Some("secret")
+        );
+        // :secret
+        assert_eq!(
+            basic_password("Basic OnNlY3JldA==").as_deref(),
+            Some("secret")
+        );
+        // admin:
+        assert_eq!(basic_password("Basic YWRtaW46"), None);
+        assert_eq!(basic_password("Basic !!"), None);
+        assert_eq!(basic_password("Bearer YWRtaW46c2VjcmV0"), None);
+    }
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"abc", b"abc"));
+        assert!(!constant_time_eq(b"abc", b"abd"));
+        assert!(!constant_time_eq(b"abc", b"ab"));
+    }
+}
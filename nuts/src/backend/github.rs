@@ -1,51 +1,282 @@
-use crate::backend::{Backend, Release};
+use crate::backend::metadata::Metadata;
+use crate::backend::{is_update, Backend, Release, SyncStatus};
+use crate::clock;
 use crate::error::ErrorKind;
-use crate::{Platform, Version};
-use failure::{Error, Fail};
+use crate::events::{self, Asset, Events, ReleaseEvent};
+use crate::metrics;
+use crate::policy::Policy;
+use crate::{Client, Platform, Version};
+use failure::Error;
 use reqwest::Response;
 use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// State of an asset that has been fully uploaded to a release.
+const ASSET_STATE_UPLOADED: &str = "uploaded";
+
+/// How long the release index is used before it is fetched from Github again.
+pub const DEFAULT_INDEX_TTL: Duration = Duration::from_secs(60);
+
+/// How long the previous release index is used after fetching it failed, before Github is asked
+/// again.
+const RETRY_DELAY: Duration = Duration::from_secs(10);
+
+/// How long the remaining Github rate limit is reported before it is fetched again.
+const RATE_LIMIT_TTL: Duration = Duration::from_secs(60);
+
+/// The shortest interval at which the releases can be polled, shorter intervals would run into
+/// the Github rate limit.
+pub const MIN_POLL_INTERVAL: Duration = Duration::from_secs(10);
 
 pub struct Config {
     pub repo: String,
     pub token: Option<String>,
+
+    /// Serve draft releases, these are only visible to authenticated tokens.
+    pub include_drafts: bool,
+
+    /// Channel of releases marked as pre-release on Github whose tag has no pre-release part,
+    /// `None` when they are stable releases.
+    pub prerelease_channel: Option<String>,
+
+    /// How long the release index is used before it is fetched again.
+    pub index_ttl: Duration,
 }
 
+/// A backend serving the releases of a Github repository. Clones share the release index, so a
+/// clone can keep the index up to date from another thread.
+#[derive(Clone)]
 pub struct Github {
     repo: String,
+    include_drafts: bool,
+    prerelease_channel: Option<String>,
     config: octokit::Config,
+    index_ttl: Duration,
+    index: Arc<RwLock<Option<Index>>>,
+
+    /// Held while the releases are fetched, so only one fetch runs at a time.
+    refreshing: Arc<Mutex<()>>,
+
+    /// When fetching the releases last failed, cleared by a successful fetch.
+    failed_at: Arc<Mutex<Option<Instant>>>,
+
+    /// The remaining Github rate limit and when it was fetched.
+    rate_limit: Arc<Mutex<Option<(u32, Instant)>>>,
+    sync_status: Arc<Mutex<SyncStatus>>,
+    events: Arc<Events>,
+}
+
+/// The releases as last fetched from Github.
+struct Index {
+    releases: Vec<GithubRelease>,
+
+    /// The releases as compared when the index is fetched again.
+    assets: Vec<Asset>,
+    fetched_at: Instant,
 }
 
 impl Github {
     pub fn new(cfg: Config) -> Self {
         Github {
             repo: cfg.repo,
+            include_drafts: cfg.include_drafts,
+            prerelease_channel: cfg.prerelease_channel,
             config: octokit::Config {
                 auth: cfg.token,
                 ..octokit::Config::default()
             },
+            index_ttl: cfg.index_ttl,
+            index: Default::default(),
+            refreshing: Default::default(),
+            failed_at: Default::default(),
+            rate_limit: Default::default(),
+            sync_status: Default::default(),
+            events: Default::default(),
         }
     }
 
-    // TODO: error handling
-    // TODO: this should be sorted on semver.
+    /// The changes to the release index, which are published whenever it is fetched again.
+    pub fn events(&self) -> &Events {
+        &self.events
+    }
+
+    /// Fetches the releases from Github every interval, so changes are picked up without waiting
+    /// for a request to find the index expired.
+    pub fn spawn_poller(&self, interval: Duration) -> thread::JoinHandle<()> {
+        let backend = self.clone();
+        thread::Builder::new()
+            .name("release-poller".to_string())
+            .spawn(move || loop {
+                if let Err(e) = backend.refresh() {
+                    warn!(repository = backend.repo, error:% = e; "could not poll releases");
+                }
+                thread::sleep(interval);
+            })
+            .expect("could not start release poller")
+    }
+
+    /// Fetches the releases from Github and swaps them in as the new index. Returns how the index
+    /// changed, the first index is not compared to anything so it yields no events. A refresh
+    /// that has to wait for another one uses its index, or fails when that one failed, instead of
+    /// fetching again.
+    pub fn refresh(&self) -> Result<Vec<ReleaseEvent>, Error> {
+        let requested_at = Instant::now();
+        let _refreshing = self.refreshing.lock().unwrap();
+        if let Some(index) = &*self.index.read().unwrap() {
+            if index.fetched_at >= requested_at {
+                return Ok(vec![]);
+            }
+        }
+        if self
+            .failed_at
+            .lock()
+            .unwrap()
+            .map_or(false, |x| x >= requested_at)
+        {
+            bail!("{}", self.last_error());
+        }
+
+        let releases = match self.fetch_releases() {
+            Ok(releases) => releases,
+            Err(e) => {
+                self.sync_status.lock().unwrap().last_error = Some(e.to_string());
+                *self.failed_at.lock().unwrap() = Some(Instant::now());
+                return Err(e);
+            }
+        };
+        *self.failed_at.lock().unwrap() = None;
+
+        let new: Vec<Asset> = releases.iter().map(|x| Asset::new(x)).collect();
+        let changes = {
+            let mut index = self.index.write().unwrap();
+            let changes = match &*index {
+                Some(old) => events::diff(&old.assets, &new),
+                None => vec![],
+            };
+            *self.sync_status.lock().unwrap() = SyncStatus {
+                last_success: Some(clock::now()),
+                last_error: None,
+                releases: releases.len(),
+            };
+            *index = Some(Index {
+                releases,
+                assets: new,
+                fetched_at: Instant::now(),
+            });
+            changes
+        };
+
+        for event in &changes {
+            let asset = event.asset();
+            info!(
+                kind = event.kind(),
+                version = asset.version,
+                filename = asset.filename;
+                "release index changed"
+            );
+        }
+        self.events.publish(&changes);
+
+        Ok(changes)
+    }
+
+    /// Returns all release assets from the index, it is fetched again once it expires. The
+    /// previous index keeps being used when Github can not be reached, without asking Github
+    /// again until the retry delay has passed.
     fn get_releases(&self) -> Result<Vec<GithubRelease>, Error> {
-        let releases = octokit::endpoint::repos::list_releases(&self.config, &self.repo)?;
+        let retrying = self
+            .failed_at
+            .lock()
+            .unwrap()
+            .map_or(false, |x| x.elapsed() < RETRY_DELAY);
+        if let Some(index) = &*self.index.read().unwrap() {
+            if index.fetched_at.elapsed() < self.index_ttl || retrying {
+                return Ok(index.releases.clone());
+            }
+        }
+        if retrying {
+            bail!("{}", self.last_error());
+        }
+
+        let refreshed = self.refresh();
+        match (&*self.index.read().unwrap(), refreshed) {
+            (Some(index), _) => Ok(index.releases.clone()),
+            (None, Err(e)) => Err(e),
+            (None, Ok(_)) => unreachable!("index is set by a successful refresh"),
+        }
+    }
+
+    /// Returns the error of the last failed fetch.
+    fn last_error(&self) -> String {
+        let status = self.sync_status.lock().unwrap();
+        status.last_error.clone().unwrap_or_default()
+    }
+
+    /// Fetches all release assets from Github, sorted on version from new to old.
+    fn fetch_releases(&self) -> Result<Vec<GithubRelease>, Error> {
+        let releases = metrics::github_request("list_releases", || {
+            octokit::endpoint::repos::list_releases(&self.config, &self.repo)
+        })?;
         let mut out = vec![];
         for gh_release in releases {
+            if gh_release.draft && !self.include_drafts {
+                continue;
+            }
+
+            let version = Version::from(&gh_release.tag_name)?;
+            let channel = release_channel(
+                &version,
+                gh_release.prerelease,
+                self.prerelease_channel.as_deref(),
+            );
+            let body = gh_release.body.as_ref().map_or("", String::as_str);
+            let metadata = Metadata::parse(body);
+            let notes = Some(Metadata::strip(body)).filter(|x| !x.is_empty());
             for gh_asset in gh_release.assets {
+                if gh_asset.state != ASSET_STATE_UPLOADED {
+                    continue;
+                }
+
                 out.push(GithubRelease {
                     platform: Platform::detect_from_filename(&gh_asset.name)?,
-                    version: Version::from(&gh_release.tag_name)?,
+                    version: version.clone(),
+                    channel: channel.clone(),
+                    notes: notes.clone(),
                     filename: PathBuf::from(gh_asset.name),
-                    download_url: gh_asset.url,
                     asset_id: gh_asset.id,
+                    rollout: metadata.rollout,
+                    critical: metadata.critical,
+                    size: u64::from(gh_asset.size),
+                    checksum: gh_asset.digest,
+                    published_at: gh_release.published_at.clone(),
                 });
             }
         }
 
+        out.sort_by(|a, b| b.version.inner_version().cmp(a.version.inner_version()));
         Ok(out)
     }
 
+    /// Returns the number of requests left in the current Github rate limit window, it is
+    /// fetched at most once per minute.
+    pub fn rate_limit_remaining(&self) -> Result<u32, Error> {
+        let mut cached = self.rate_limit.lock().unwrap();
+        if let Some((remaining, fetched_at)) = *cached {
+            if fetched_at.elapsed() < RATE_LIMIT_TTL {
+                return Ok(remaining);
+            }
+        }
+
+        let rate_limit = metrics::github_request("rate_limit", || {
+            octokit::endpoint::rate_limit::get_rate_limit(&self.config)
+        })?;
+        *cached = Some((rate_limit.rate.remaining, Instant::now()));
+
+        Ok(rate_limit.rate.remaining)
+    }
+
     fn get_release_by_predicate(
         &self,
         f: &dyn Fn(&GithubRelease) -> bool,
@@ -66,15 +297,23 @@ impl Backend for Github {
         &self,
         platform: Platform,
         version: Version,
+        client: &Client,
+        policy: &Policy,
     ) -> Result<Box<dyn Release>, Error> {
         self.get_release_by_predicate(&|x: &GithubRelease| {
-            *x.get_platform() == platform
-                && x.get_version().channel() == version.channel()
-                && *x.get_version().inner_version() > *version.inner_version()
+            is_update(x, platform, &version, client, policy)
         })
         .map(|x| Box::new(x) as Box<dyn Release>)
     }
 
+    fn list_releases(&self) -> Result<Vec<Box<dyn Release>>, Error> {
+        Ok(self
+            .get_releases()?
+            .into_iter()
+            .map(|x| Box::new(x) as Box<dyn Release>)
+            .collect())
+    }
+
     fn get_release_by_filename(&self, filename: String) -> Result<Box<dyn Release>, Error> {
         self.get_release_by_predicate(&|x: &GithubRelease| {
             *x.get_filename() == PathBuf::from(filename.as_str())
@@ -87,19 +326,44 @@ impl Backend for Github {
             *x.get_filename() == PathBuf::from(filename)
         })?;
 
-        let response =
-            octokit::endpoint::repos::download_asset(&self.config, &self.repo, release.asset_id)?;
+        let response = metrics::github_request("download_asset", || {
+            octokit::endpoint::repos::download_asset(&self.config, &self.repo, release.asset_id)
+        })?;
         Ok(response)
     }
+
+    fn sync_status(&self) -> SyncStatus {
+        self.sync_status.lock().unwrap().clone()
+    }
+}
+
+/// Returns the channel of a release, releases marked as pre-release on Github end up in the
+/// pre-release channel when their tag has no pre-release part.
+fn release_channel(
+    version: &Version,
+    prerelease: bool,
+    prerelease_channel: Option<&str>,
+) -> Option<String> {
+    match version.channel() {
+        Some(channel) => Some(channel),
+        None if prerelease => prerelease_channel.map(str::to_string),
+        None => None,
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct GithubRelease {
     platform: Platform,
     version: Version,
+    channel: Option<String>,
+    notes: Option<String>,
     filename: PathBuf,
-    download_url: String,
     asset_id: u32,
+    rollout: Option<u8>,
+    critical: bool,
+    size: u64,
+    checksum: Option<String>,
+    published_at: Option<String>,
 }
 
 impl Release for GithubRelease {
@@ -114,4 +378,160 @@ impl Release for GithubRelease {
     fn get_filename(&self) -> &PathBuf {
         &self.filename
     }
+
+    fn get_channel(&self) -> Option<&str> {
+        self.channel.as_deref()
+    }
+
+    fn get_notes(&self) -> Option<&str> {
+        self.notes.as_deref()
+    }
+
+    fn get_rollout(&self) -> Option<u8> {
+        self.rollout
+    }
+
+    fn is_critical(&self) -> bool {
+        self.critical
+    }
+
+    fn get_size(&self) -> Option<u64> {
+        Some(self.size)
+    }
+
+    fn get_checksum(&self) -> Option<&str> {
+        self.checksum.as_deref()
+    }
+
+    fn get_published_at(&self) -> Option<&str> {
+        self.published_at.as_deref()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_server;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Barrier;
+
+    /// Returns a backend that fetches from a test server instead of Github.
+    fn backend(url: &str) -> Github {
+        backend_with_ttl(url, Duration::from_secs(60))
+    }
+
+    fn backend_with_ttl(url: &str, index_ttl: Duration) -> Github {
+        let mut backend = Github::new(Config {
+            repo: "tacitic/app".to_string(),
+            token: None,
+            include_drafts: false,
+            prerelease_channel: Some("beta".to_string()),
+            index_ttl,
+        });
+        backend.config.base_url = url.parse().unwrap();
+        backend
+    }
+
+    #[test]
+    fn test_release_channel() {
+        let v = |x| Version::from(x).unwrap();
+        assert_eq!(release_channel(&v("1.2.0"), false, Some("beta")), None);
+        assert_eq!(
+            release_channel(&v("1.2.0"), true, Some("beta")),
+            Some("beta".to_string())
+        );
+        assert_eq!(
+            release_channel(&v("1.2.0-alpha.1"), true, Some("beta")),
+            Some("alpha".to_string())
+        );
+        assert_eq!(release_channel(&v("1.2.0"), true, None), None);
+    }
+
+    #[test]
+    fn test_refresh_once() {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        let url = test_server::serve(move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(200));
+            (200, "[]".to_string())
+        });
+
+        let backend = backend(&url);
+
+        // Refreshes that wait for a running one use its index.
+        let barrier = Arc::new(Barrier::new(4));
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let backend = backend.clone();
+                let barrier = barrier.clone();
+                thread::spawn(move || {
+                    barrier.wait();
+                    backend.refresh().unwrap();
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        backend.refresh().unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_stale_index_when_github_fails() {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        let url = test_server::serve(move |_| {
+            if counter.fetch_add(1, Ordering::SeqCst) == 0 {
+                return (200, "[]".to_string());
+            }
+            thread::sleep(Duration::from_millis(200));
+            (502, "".to_string())
+        });
+
+        let backend = backend_with_ttl(&url, Duration::from_secs(0));
+        backend.get_releases().unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        // Requests that find the index expired while Github fails wait for one fetch at most.
+        let barrier = Arc::new(Barrier::new(4));
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let backend = backend.clone();
+                let barrier = barrier.clone();
+                thread::spawn(move || {
+                    barrier.wait();
+                    backend.get_releases().unwrap();
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+
+        // The stale index is served without asking Github until the retry delay has passed.
+        backend.get_releases().unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+        assert!(backend.sync_status().last_error.is_some());
+    }
+
+    #[test]
+    fn test_rate_limit_remaining() {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        let url = test_server::serve(move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            let rate = r#"{"rate": {"limit": 5000, "remaining": 4999, "reset": 0}}"#;
+            (200, rate.to_string())
+        });
+
+        let backend = backend(&url);
+        assert_eq!(backend.rate_limit_remaining().unwrap(), 4999);
+        assert_eq!(backend.rate_limit_remaining().unwrap(), 4999);
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
 }
@@ -0,0 +1,131 @@
+/// Prefix that marks an html comment as a nuts metadata entry.
+const PREFIX: &str = "nuts:";
+
+/// Metadata that can be embedded in the body of a release using html comments, these are not
+/// rendered by Github. For example:
+///
+/// ```text
+/// <!-- nuts:rollout=5 -->
+/// ```
+#[derive(Debug, Default, PartialEq)]
+pub struct Metadata {
+    /// Percentage of clients this release is offered to.
+    pub rollout: Option<u8>,
+
+    /// Clients skipping this release must update, set with `<!-- nuts:critical -->`.
+    pub critical: bool,
+}
+
+impl Metadata {
+    /// Parses the metadata from a release body, unknown or malformed entries are ignored.
+    pub fn parse(body: &str) -> Self {
+        let mut out = Metadata::default();
+
+        for (key, value) in entries(body) {
+            match (key, value) {
+                ("rollout", Some(v)) => {
+                    out.rollout = v.trim_end_matches('%').parse().ok().map(|x: u8| x.min(100))
+                }
+                ("critical", None) => out.critical = true,
+                ("critical", Some(v)) => out.critical = v == "true",
+                _ => {}
+            }
+        }
+
+        out
+    }
+
+    /// Returns the release body without the metadata entries.
+    pub fn strip(body: &str) -> String {
+        let mut out = String::new();
+        let mut rest = body;
+
+        while let Some(start) = rest.find("<!--") {
+            let end = match rest[start..].find("-->") {
+                Some(end) => start + end + 3,
+                None => break,
+            };
+
+            out.push_str(&rest[..start]);
+            if !rest[start + 4..end - 3].trim().starts_with(PREFIX) {
+                out.push_str(&rest[start..end]);
+            }
+            rest = &rest[end..];
+        }
+
+        out.push_str(rest);
+        out.trim().to_string()
+    }
+}
+
+/// Returns all `key[=value]` entries found in a release body.
+fn entries(body: &str) -> Vec<(&str, Option<&str>)> {
+    let mut out = vec![];
+    let mut rest = body;
+
+    while let Some(start) = rest.find("<!--") {
+        rest = &rest[start + 4..];
+        let end = match rest.find("-->") {
+            Some(end) => end,
+            None => break,
+        };
+
+        let comment = rest[..end].trim();
+        rest = &rest[end + 3..];
+
+        if let Some(entry) = comment.strip_prefix(PREFIX) {
+            out.push(match entry.find('=') {
+                Some(i) => (entry[..i].trim(), Some(entry[i + 1..].trim())),
+                None => (entry.trim(), None),
+            });
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_rollout() {
+        let tests = vec![
+            ("", None),
+            ("Some release notes", None),
+            ("<!-- nuts:rollout=5 -->", Some(5)),
+            ("Fixes\n\n<!--nuts:rollout = 25% -->\n", Some(25)),
+            ("<!-- nuts:rollout=250 -->", Some(100)),
+            ("<!-- nuts:rollout=abc -->", None),
+            ("<!-- nuts:rollout=5", None),
+        ];
+
+        for (body, expect) in tests {
+            assert_eq!(Metadata::parse(body).rollout, expect, "{}", body);
+        }
+    }
+
+    #[test]
+    fn test_parse_critical() {
+        assert!(!Metadata::parse("Some release notes").critical);
+        assert!(Metadata::parse("<!-- nuts:critical -->").critical);
+        assert!(Metadata::parse("<!-- nuts:critical=true -->").critical);
+        assert!(!Metadata::parse("<!-- nuts:critical=false -->").critical);
+
+        let metadata = Metadata::parse("<!-- nuts:rollout=10 -->\n<!-- nuts:critical -->");
+        assert_eq!(metadata.rollout, Some(10));
+        assert!(metadata.critical);
+    }
+
+    #[test]
+    fn test_strip() {
+        assert_eq!(
+            Metadata::strip("<!-- nuts:critical -->\r\n# Fixes\n<!-- todo -->\n"),
+            "# Fixes\n<!-- todo -->"
+        );
+        assert_eq!(
+            Metadata::strip("Fixes <!-- nuts:rollout=5"),
+            "Fixes <!-- nuts:rollout=5"
+        );
+    }
+}
@@ -1,24 +1,197 @@
-use crate::{Platform, Version};
+use crate::policy::Policy;
+use crate::{Arch, Client, PackageType, Platform, Version, STABLE_CHANNEL};
 use failure::Error;
 use reqwest::Response;
+use serde::Serialize;
+use std::iter;
 use std::path::PathBuf;
 
 pub mod github;
+pub mod metadata;
+#[cfg(test)]
+pub mod test_util;
+
+/// The architecture of downloads for clients whose architecture is unknown.
+pub const DEFAULT_ARCH: Arch = Arch::X64;
 
 pub trait Backend {
     fn resolve_release(
         &self,
         platform: Platform,
         version: Version,
+        client: &Client,
+        policy: &Policy,
     ) -> Result<Box<dyn Release>, Error>;
 
+    /// Returns all releases, sorted on version from new to old.
+    fn list_releases(&self) -> Result<Vec<Box<dyn Release>>, Error>;
+
     fn get_release_by_filename(&self, filename: String) -> Result<Box<dyn Release>, Error>;
 
     fn download(&self, filename: &str) -> Result<Response, Error>;
+
+    /// Returns the state of the release index and the outcome of the last sync.
+    fn sync_status(&self) -> SyncStatus;
+}
+
+/// Returns whether a client on a version of a platform can update to a release. The update is
+/// the newest release for which this holds.
+pub fn is_update(
+    release: &dyn Release,
+    platform: Platform,
+    version: &Version,
+    client: &Client,
+    policy: &Policy,
+) -> bool {
+    let channel = client.channel_for(version);
+    let package_type = PackageType::for_update(platform);
+
+    *release.get_platform() == platform
+        && package_type.map_or(true, |p| release.get_package_type() == Some(p))
+        && policy.accepts_channel(channel.as_deref(), policy.channel(release))
+        && (*release.get_version().inner_version() > *version.inner_version()
+            || policy.is_yanked(version))
+        && policy.is_eligible(release, client)
+}
+
+/// Returns the newest of the releases for a platform and architecture that is distributed as
+/// one of the given package types, earlier package types are preferred within a version.
+/// Universal builds match every architecture, they are preferred when the architecture is
+/// unknown and releases for the default architecture are offered otherwise. Releases are expected
+/// to be sorted from new to old.
+pub fn latest<'a>(
+    releases: &'a [Box<dyn Release>],
+    platform: Platform,
+    arch: Option<Arch>,
+    package_types: &[PackageType],
+    client: &Client,
+    policy: &Policy,
+) -> Option<&'a dyn Release> {
+    let channel = client.channel.as_deref().filter(|x| *x != STABLE_CHANNEL);
+    let preference = |x: &dyn Release| {
+        x.get_package_type()
+            .and_then(|p| package_types.iter().position(|t| *t == p))
+    };
+
+    let mut candidates = releases.iter().map(|x| x.as_ref()).filter(|x| {
+        *x.get_platform() == platform
+            && x.get_arch()
+                .map_or(true, |a| a == arch.unwrap_or(DEFAULT_ARCH))
+            && preference(*x).is_some()
+            && policy.accepts_channel(channel, policy.channel(*x))
+            && policy.is_eligible(*x, client)
+    });
+
+    let newest = candidates.next()?;
+    let version = newest.get_version().inner_version();
+    iter::once(newest)
+        .chain(candidates.take_while(|x| x.get_version().inner_version() == version))
+        // Builds for the architecture, or universal builds when it is unknown, come first.
+        .min_by_key(|x| (preference(*x), x.get_arch().is_some() != arch.is_some()))
+}
+
+/// The state of the release index of a backend.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SyncStatus {
+    /// Unix timestamp of the last successful sync, `None` when no index is loaded.
+    pub last_success: Option<i64>,
+
+    /// The error of the last sync, cleared once a sync succeeds again.
+    pub last_error: Option<String>,
+
+    /// The number of release assets in the index.
+    pub releases: usize,
 }
 
 pub trait Release {
     fn get_platform(&self) -> &Platform;
     fn get_version(&self) -> &Version;
     fn get_filename(&self) -> &PathBuf;
+
+    /// Returns the architecture of the release, `None` when it is not specific to one.
+    fn get_arch(&self) -> Option<Arch> {
+        self.get_filename()
+            .to_str()
+            .and_then(Arch::detect_from_filename)
+    }
+
+    fn get_package_type(&self) -> Option<PackageType> {
+        self.get_filename()
+            .to_str()
+            .and_then(PackageType::detect_from_filename)
+    }
+
+    /// Returns the channel of the release, `None` for the stable channel.
+    fn get_channel(&self) -> Option<&str>;
+    /// Returns the release notes in markdown.
+    fn get_notes(&self) -> Option<&str>;
+    fn get_rollout(&self) -> Option<u8>;
+    fn is_critical(&self) -> bool;
+
+    /// Returns the size of the release asset in bytes.
+    fn get_size(&self) -> Option<u64> {
+        None
+    }
+
+    /// Returns the checksum of the release asset, prefixed with its algorithm, e.g. 'sha256:..'.
+    fn get_checksum(&self) -> Option<&str> {
+        None
+    }
+
+    /// Returns when the release was published, as an RFC 3339 timestamp.
+    fn get_published_at(&self) -> Option<&str> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backend::test_util::release;
+
+    #[test]
+    fn test_latest() {
+        let releases = vec![
+            release("app-1.3.0-mac-arm64.zip"),
+            release("app-1.3.0-mac-x64.dmg"),
+            release("app-1.3.0-mac-arm64.dmg"),
+            release("app-1.3.0-mac.dmg"),
+            release("app-1.2.0-mac-ia32.dmg"),
+        ];
+        let client = Client::default();
+        let policy = Policy::default();
+        let resolve = |arch| {
+            let package_types = PackageType::for_download(Platform::MacOS);
+            latest(
+                &releases,
+                Platform::MacOS,
+                arch,
+                package_types,
+                &client,
+                &policy,
+            )
+            .map(|x| x.get_filename().to_string_lossy().to_string())
+        };
+
+        assert_eq!(
+            resolve(Some(Arch::Arm64)).unwrap(),
+            "app-1.3.0-mac-arm64.dmg"
+        );
+        assert_eq!(resolve(Some(Arch::X64)).unwrap(), "app-1.3.0-mac-x64.dmg");
+        assert_eq!(resolve(None).unwrap(), "app-1.3.0-mac.dmg");
+        assert_eq!(resolve(Some(Arch::Ia32)).unwrap(), "app-1.3.0-mac.dmg");
+
+        // Builds for another architecture are not offered.
+        let releases = vec![release("app-1.3.0-mac-arm64.dmg")];
+        let package_types = PackageType::for_download(Platform::MacOS);
+        assert!(latest(
+            &releases,
+            Platform::MacOS,
+            None,
+            package_types,
+            &client,
+            &policy
+        )
+        .is_none());
+    }
 }
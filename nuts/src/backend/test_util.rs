@@ -0,0 +1,68 @@
+use crate::backend::Release;
+use crate::{Platform, Version};
+use std::path::PathBuf;
+
+/// A release for tests, the platform and version are taken from a filename like
+/// 'app-1.2.0-mac-x64.zip'.
+#[derive(Debug, Clone)]
+pub struct TestRelease {
+    pub platform: Platform,
+    pub version: Version,
+    pub filename: PathBuf,
+    pub channel: Option<String>,
+    pub notes: Option<String>,
+    pub critical: bool,
+}
+
+impl TestRelease {
+    pub fn new(filename: &str) -> Self {
+        let version = filename.split('-').nth(1).unwrap();
+        TestRelease {
+            platform: Platform::detect_from_filename(filename).unwrap(),
+            version: Version::from(version).unwrap(),
+            filename: PathBuf::from(filename),
+            channel: None,
+            notes: None,
+            critical: false,
+        }
+    }
+
+    pub fn boxed(self) -> Box<dyn Release> {
+        Box::new(self)
+    }
+}
+
+impl Release for TestRelease {
+    fn get_platform(&self) -> &Platform {
+        &self.platform
+    }
+
+    fn get_version(&self) -> &Version {
+        &self.version
+    }
+
+    fn get_filename(&self) -> &PathBuf {
+        &self.filename
+    }
+
+    fn get_channel(&self) -> Option<&str> {
+        self.channel.as_deref()
+    }
+
+    fn get_notes(&self) -> Option<&str> {
+        self.notes.as_deref()
+    }
+
+    fn get_rollout(&self) -> Option<u8> {
+        None
+    }
+
+    fn is_critical(&self) -> bool {
+        self.critical
+    }
+}
+
+/// Returns a release for a filename like 'app-1.2.0-mac-x64.zip'.
+pub fn release(filename: &str) -> Box<dyn Release> {
+    TestRelease::new(filename).boxed()
+}
@@ -0,0 +1,283 @@
+use crate::backend::{Backend, Release};
+use crate::Version;
+use failure::Error;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use tempfile::NamedTempFile;
+
+/// How many assets are downloaded at the same time when warming the cache.
+pub const DEFAULT_WARM_CONCURRENCY: usize = 2;
+
+/// The release assets in the cache directory, which are cached under their filename.
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct Usage {
+    pub files: usize,
+    pub bytes: u64,
+}
+
+/// Returns how many of the given release assets are cached and their total size.
+pub fn usage(dir: &Path, filenames: &[String]) -> Usage {
+    let mut out = Usage::default();
+    for filename in filenames {
+        if let Ok(metadata) = fs::metadata(dir.join(filename)) {
+            out.files += 1;
+            out.bytes += metadata.len();
+        }
+    }
+
+    out
+}
+
+/// Removes the given release assets from the cache, returns what was removed. Other files in the
+/// cache directory, like the policy overrides, are left alone.
+pub fn purge(dir: &Path, filenames: &[String]) -> Result<Usage, Error> {
+    let mut out = Usage::default();
+    for filename in filenames {
+        let path = dir.join(filename);
+        let size = match fs::metadata(&path) {
+            Ok(metadata) => metadata.len(),
+            Err(_) => continue,
+        };
+
+        match fs::remove_file(&path) {
+            Ok(()) => {
+                out.files += 1;
+                out.bytes += size;
+            }
+            // Removed by a concurrent purge.
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    Ok(out)
+}
+
+/// Downloads a release asset into the cache unless it is cached already, returns whether it was
+/// downloaded. The asset is moved into place once it is complete, so a partial download is never
+/// served.
+pub fn fetch(backend: &dyn Backend, dir: &Path, filename: &str) -> Result<bool, Error> {
+    let path = dir.join(filename);
+    if fs::metadata(&path).is_ok() {
+        return Ok(false);
+    }
+
+    let mut file = NamedTempFile::new_in(dir)?;
+    backend.download(filename)?.copy_to(&mut file)?;
+    file.persist(&path)?;
+
+    Ok(true)
+}
+
+/// Returns the filenames of the release assets of a version.
+pub fn version_filenames(releases: &[Box<dyn Release>], version: &Version) -> Vec<String> {
+    releases
+        .iter()
+        .filter(|x| x.get_version().inner_version() == version.inner_version())
+        .map(|x| x.get_filename().to_string_lossy().to_string())
+        .collect()
+}
+
+/// Downloads release assets into the cache in the background, so the first client to request an
+/// asset does not wait for the backend. Clones share the queue.
+#[derive(Clone)]
+pub struct Warmer {
+    sender: mpsc::Sender<String>,
+    queued: Arc<Mutex<HashSet<String>>>,
+}
+
+impl Warmer {
+    /// Starts the threads that download the queued assets, at most `concurrency` at a time.
+    pub fn start<B>(backend: B, dir: PathBuf, concurrency: usize) -> Self
+    where
+        B: Backend + Clone + Send + 'static,
+    {
+        let (sender, receiver) = mpsc::channel::<String>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let queued = Arc::new(Mutex::new(HashSet::new()));
+
+        for i in 0..concurrency.max(1) {
+            let backend = backend.clone();
+            let dir = dir.clone();
+            let receiver = receiver.clone();
+            let queued = queued.clone();
+            thread::Builder::new()
+                .name(format!("cache-warmer-{}", i))
+                .spawn(move || loop {
+                    let filename = match receiver.lock().unwrap().recv() {
+                        Ok(filename) => filename,
+                        Err(_) => return,
+                    };
+                    match fetch(&backend, &dir, &filename) {
+                        Ok(true) => info!(filename; "warmed asset cache"),
+                        Ok(false) => debug!(filename; "asset already cached"),
+                        Err(e) => warn!(filename, error:% = e; "could not warm asset cache"),
+                    }
+                    queued.lock().unwrap().remove(&filename);
+                })
+                .expect("could not start cache warmer");
+        }
+
+        Warmer { sender, queued }
+    }
+
+    /// Queues an asset to be downloaded, returns `false` when it is queued already.
+    pub fn warm(&self, filename: &str) -> bool {
+        if !self.queued.lock().unwrap().insert(filename.to_string()) {
+            return false;
+        }
+
+        self.sender.send(filename.to_string()).is_ok()
+    }
+}
+
+/// Formats a number of bytes for humans, e.g. '1.5 MB'.
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: &[&str] = &["B", "KB", "MB", "GB", "TB"];
+
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1000.0 && unit < UNITS.len() - 1 {
+        value /= 1000.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backend::SyncStatus;
+    use crate::policy::Policy;
+    use crate::test_server;
+    use crate::{Client, Platform};
+    use reqwest::Response;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::{Duration, Instant};
+
+    /// A backend that downloads every asset from a local server.
+    #[derive(Clone)]
+    struct TestBackend {
+        url: String,
+    }
+
+    impl Backend for TestBackend {
+        fn resolve_release(
+            &self,
+            _: Platform,
+            _: Version,
+            _: &Client,
+            _: &Policy,
+        ) -> Result<Box<dyn Release>, Error> {
+            unreachable!()
+        }
+
+        fn list_releases(&self) -> Result<Vec<Box<dyn Release>>, Error> {
+            unreachable!()
+        }
+
+        fn get_release_by_filename(&self, _: String) -> Result<Box<dyn Release>, Error> {
+            unreachable!()
+        }
+
+        fn download(&self, filename: &str) -> Result<Response, Error> {
+            Ok(reqwest::get(&format!("{}/{}", self.url, filename))?)
+        }
+
+        fn sync_status(&self) -> SyncStatus {
+            SyncStatus::default()
+        }
+    }
+
+    /// Starts a server that answers every request with the same asset, the returned counter holds
+    /// the number of requests it received.
+    fn serve() -> (TestBackend, Arc<AtomicUsize>) {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        let url = test_server::serve(move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            (200, "asset".to_string())
+        });
+
+        (TestBackend { url }, requests)
+    }
+
+    #[test]
+    fn test_fetch() {
+        let dir = tempfile::tempdir().unwrap();
+        let (backend, requests) = serve();
+
+        assert!(fetch(&backend, dir.path(), "app-1.0.0-mac.zip").unwrap());
+        assert!(!fetch(&backend, dir.path(), "app-1.0.0-mac.zip").unwrap());
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+        assert_eq!(
+            fs::read_to_string(dir.path().join("app-1.0.0-mac.zip")).unwrap(),
+            "asset"
+        );
+    }
+
+    #[test]
+    fn test_warmer() {
+        let dir = tempfile::tempdir().unwrap();
+        let (backend, _) = serve();
+
+        let warmer = Warmer::start(backend, dir.path().to_path_buf(), 2);
+        let filenames = vec![
+            "app-1.0.0-mac.zip".to_string(),
+            "app-1.0.0-win.exe".to_string(),
+            "app-1.0.0-linux.AppImage".to_string(),
+        ];
+        for filename in &filenames {
+            assert!(warmer.warm(filename));
+        }
+
+        let started = Instant::now();
+        while usage(dir.path(), &filenames).files < 3 {
+            assert!(started.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(usage(dir.path(), &filenames).bytes, 15);
+    }
+
+    #[test]
+    fn test_usage_and_purge() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("app-1.0.0-mac.zip"), vec![0; 100]).unwrap();
+        fs::write(dir.path().join("app-1.0.0-win.exe"), vec![0; 50]).unwrap();
+        fs::write(dir.path().join("policy.json"), "{}").unwrap();
+
+        let filenames = vec![
+            "app-1.0.0-mac.zip".to_string(),
+            "app-1.0.0-win.exe".to_string(),
+            "app-1.0.0-linux.AppImage".to_string(),
+        ];
+        assert_eq!(
+            usage(dir.path(), &filenames),
+            Usage {
+                files: 2,
+                bytes: 150
+            }
+        );
+
+        assert_eq!(purge(dir.path(), &filenames).unwrap().bytes, 150);
+        assert_eq!(usage(dir.path(), &filenames), Usage::default());
+        assert!(dir.path().join("policy.json").exists());
+    }
+
+    #[test]
+    fn test_format_bytes() {
+        assert_eq!(format_bytes(999), "999 B");
+        assert_eq!(format_bytes(1_500_000), "1.5 MB");
+        assert_eq!(format_bytes(2_000_000_000), "2.0 GB");
+    }
+}
@@ -0,0 +1,250 @@
+use crate::backend::Release;
+use crate::policy::Policy;
+use crate::{Arch, Platform, Version, STABLE_CHANNEL};
+use rocket::FromForm;
+use serde::Serialize;
+
+/// The number of items in a page when no limit is given.
+pub const DEFAULT_PAGE_SIZE: usize = 50;
+
+/// The maximum number of items in a page.
+pub const MAX_PAGE_SIZE: usize = 500;
+
+/// The query parameters accepted by the catalogue endpoints.
+#[derive(Debug, Default, FromForm)]
+pub struct Query {
+    pub platform: Option<Platform>,
+    pub arch: Option<Arch>,
+    pub channel: Option<String>,
+    pub yanked: Option<bool>,
+    pub offset: Option<usize>,
+    pub limit: Option<usize>,
+}
+
+impl Query {
+    pub fn filter(&self) -> Filter {
+        Filter {
+            platform: self.platform,
+            arch: self.arch,
+            channel: self.channel.as_ref().map(|x| x.to_lowercase()),
+            yanked: self.yanked,
+            version: None,
+        }
+    }
+}
+
+/// Selects releases from the index, unset fields match any release.
+#[derive(Debug, Default)]
+pub struct Filter {
+    pub platform: Option<Platform>,
+    pub arch: Option<Arch>,
+    /// The name of a channel, 'stable' matches releases without a channel.
+    pub channel: Option<String>,
+    pub yanked: Option<bool>,
+    pub version: Option<Version>,
+}
+
+impl Filter {
+    pub fn matches(&self, release: &dyn Release, policy: &Policy) -> bool {
+        self.platform.map_or(true, |x| *release.get_platform() == x)
+            && self.arch.map_or(true, |x| release.get_arch() == Some(x))
+            && self.channel.as_ref().map_or(true, |x| {
+                policy.channel(release).unwrap_or(STABLE_CHANNEL) == x.as_str()
+            })
+            && self
+                .yanked
+                .map_or(true, |x| policy.is_yanked(release.get_version()) == x)
+            && self.version.as_ref().map_or(true, |x| {
+                release.get_version().inner_version() == x.inner_version()
+            })
+    }
+}
+
+/// A release asset as exposed by the catalogue.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Entry {
+    pub version: String,
+    pub channel: String,
+    pub platform: String,
+    pub arch: Option<String>,
+    pub package_type: Option<String>,
+    pub filename: String,
+    pub size: Option<u64>,
+    pub checksum: Option<String>,
+    pub published_at: Option<String>,
+    pub rollout: u8,
+    pub yanked: bool,
+    pub critical: bool,
+}
+
+impl Entry {
+    pub fn new(release: &dyn Release, policy: &Policy) -> Self {
+        Entry {
+            version: release.get_version().to_string(),
+            channel: policy
+                .channel(release)
+                .unwrap_or(STABLE_CHANNEL)
+                .to_string(),
+            platform: release.get_platform().to_string(),
+            arch: release.get_arch().map(|x| x.to_string()),
+            package_type: release.get_package_type().map(|x| x.to_string()),
+            filename: release.get_filename().to_string_lossy().to_string(),
+            size: release.get_size(),
+            checksum: release.get_checksum().map(str::to_string),
+            published_at: release.get_published_at().map(str::to_string),
+            rollout: policy.rollout(release),
+            yanked: policy.is_yanked(release.get_version()),
+            critical: policy.is_critical(release),
+        }
+    }
+}
+
+/// A version as exposed by the catalogue, with the number of assets it has on a platform.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct VersionEntry {
+    pub version: String,
+    pub channel: String,
+    pub published_at: Option<String>,
+    pub rollout: u8,
+    pub yanked: bool,
+    pub critical: bool,
+    pub assets: usize,
+}
+
+/// A page of items, `total` is the number of items across all pages.
+#[derive(Debug, Serialize)]
+pub struct Page<T> {
+    pub total: usize,
+    pub offset: usize,
+    pub limit: usize,
+    pub items: Vec<T>,
+}
+
+impl<T> Page<T> {
+    pub fn new(items: Vec<T>, offset: Option<usize>, limit: Option<usize>) -> Self {
+        let offset = offset.unwrap_or(0);
+        let limit = limit.unwrap_or(DEFAULT_PAGE_SIZE).min(MAX_PAGE_SIZE);
+
+        Page {
+            total: items.len(),
+            offset,
+            limit,
+            items: items.into_iter().skip(offset).take(limit).collect(),
+        }
+    }
+}
+
+/// Returns the release assets matching a filter, in the order of the index.
+pub fn entries(releases: &[Box<dyn Release>], filter: &Filter, policy: &Policy) -> Vec<Entry> {
+    releases
+        .iter()
+        .filter(|x| filter.matches(x.as_ref(), policy))
+        .map(|x| Entry::new(x.as_ref(), policy))
+        .collect()
+}
+
+/// Returns the versions of the releases matching a filter. Releases are expected to be sorted
+/// on version from new to old.
+pub fn versions(
+    releases: &[Box<dyn Release>],
+    filter: &Filter,
+    policy: &Policy,
+) -> Vec<VersionEntry> {
+    let mut out: Vec<VersionEntry> = vec![];
+    for release in releases
+        .iter()
+        .filter(|x| filter.matches(x.as_ref(), policy))
+    {
+        let version = release.get_version().to_string();
+        match out.last_mut() {
+            Some(last) if last.version == version => last.assets += 1,
+            _ => out.push(VersionEntry {
+                version,
+                channel: policy
+                    .channel(release.as_ref())
+                    .unwrap_or(STABLE_CHANNEL)
+                    .to_string(),
+                published_at: release.get_published_at().map(str::to_string),
+                rollout: policy.rollout(release.as_ref()),
+                yanked: policy.is_yanked(release.get_version()),
+                critical: policy.is_critical(release.as_ref()),
+                assets: 1,
+            }),
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backend::test_util::release;
+
+    fn releases() -> Vec<Box<dyn Release>> {
+        vec![
+            release("app-1.3.0-mac-arm64.zip"),
+            release("app-1.3.0-mac-x64.zip"),
+            release("app-1.3.0-win-x64.exe"),
+            release("app-1.2.0-mac-x64.zip"),
+            release("app-1.2.0-win-x64.exe"),
+        ]
+    }
+
+    #[test]
+    fn test_entries() {
+        let policy = Policy {
+            yanked: vec!["1.2.0".to_string()].into_iter().collect(),
+            ..Policy::default()
+        };
+
+        let filter = Filter {
+            platform: Some(Platform::MacOS),
+            arch: Some(Arch::X64),
+            ..Filter::default()
+        };
+        let out = entries(&releases(), &filter, &policy);
+        assert_eq!(out.len(), 2);
+        assert_eq!(out[0].filename, "app-1.3.0-mac-x64.zip");
+        assert_eq!(out[0].package_type.as_deref(), Some("zip"));
+        assert_eq!(out[0].channel, "stable");
+        assert!(out[1].yanked);
+
+        let filter = Filter {
+            yanked: Some(false),
+            channel: Some("stable".to_string()),
+            ..Filter::default()
+        };
+        assert_eq!(entries(&releases(), &filter, &policy).len(), 3);
+
+        let filter = Filter {
+            channel: Some("beta".to_string()),
+            ..Filter::default()
+        };
+        assert!(entries(&releases(), &filter, &policy).is_empty());
+    }
+
+    #[test]
+    fn test_versions() {
+        let filter = Filter {
+            platform: Some(Platform::MacOS),
+            ..Filter::default()
+        };
+        let out = versions(&releases(), &filter, &Policy::default());
+        assert_eq!(out.len(), 2);
+        assert_eq!(out[0].version, "1.3.0");
+        assert_eq!(out[0].assets, 2);
+        assert_eq!(out[1].assets, 1);
+    }
+
+    #[test]
+    fn test_page() {
+        let page = Page::new((0..10).collect::<Vec<_>>(), Some(8), Some(5));
+        assert_eq!(page.total, 10);
+        assert_eq!(page.items, vec![8, 9]);
+
+        let page = Page::new((0..1000).collect::<Vec<_>>(), None, Some(1000));
+        assert_eq!(page.limit, MAX_PAGE_SIZE);
+        assert_eq!(page.items.len(), MAX_PAGE_SIZE);
+    }
+}
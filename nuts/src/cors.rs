@@ -0,0 +1,175 @@
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::{Method, Status};
+use rocket::{Request, Response};
+use std::io::Cursor;
+
+/// The endpoints that can be called from a browser, matched on the start of the path.
+const PATHS: &[&str] = &["/update/", "/notes/", "/api/releases", "/api/versions"];
+
+/// Methods allowed when none are configured.
+pub const DEFAULT_METHODS: &str = "GET, OPTIONS";
+
+/// Request headers allowed when none are configured.
+pub const DEFAULT_HEADERS: &str = "Authorization, X-Client-Id, X-License-Key";
+
+/// Response headers that scripts on an allowed origin may read.
+const EXPOSE_HEADERS: &str = "Retry-After, X-Request-Id";
+
+/// How long a browser may cache the answer to a preflight request, in seconds.
+const MAX_AGE: u64 = 60 * 60;
+
+/// The origins that may call the endpoints.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Origins {
+    Any,
+    List(Vec<String>),
+}
+
+/// Cors is a fairing that adds the CORS headers to responses of the update, notes and catalogue
+/// endpoints and answers preflight requests for them. It does nothing when no origins are
+/// configured.
+#[derive(Debug)]
+pub struct Cors {
+    origins: Origins,
+    methods: Vec<String>,
+    headers: Vec<String>,
+}
+
+impl Cors {
+    /// Creates the fairing from comma separated lists, '*' allows any origin.
+    pub fn new(origins: &str, methods: &str, headers: &str) -> Self {
+        let origins = if origins.trim() == "*" {
+            Origins::Any
+        } else {
+            Origins::List(
+                split(origins)
+                    .map(|x| x.trim_end_matches('/').to_lowercase())
+                    .collect(),
+            )
+        };
+
+        Cors {
+            origins,
+            methods: split(methods).map(|x| x.to_uppercase()).collect(),
+            headers: split(headers).map(str::to_string).collect(),
+        }
+    }
+
+    /// Returns the value of the 'Access-Control-Allow-Origin' header for a request origin, or
+    /// `None` when the origin is not allowed.
+    pub fn allow_origin(&self, origin: &str) -> Option<String> {
+        match &self.origins {
+            Origins::Any => Some("*".to_string()),
+            Origins::List(list) if list.iter().any(|x| x.eq_ignore_ascii_case(origin)) => {
+                Some(origin.to_string())
+            }
+            Origins::List(_) => None,
+        }
+    }
+
+    pub fn allows_method(&self, method: &str) -> bool {
+        self.methods.iter().any(|x| x.eq_ignore_ascii_case(method))
+    }
+
+    /// Returns true when CORS applies to a path.
+    pub fn applies_to(&self, path: &str) -> bool {
+        PATHS.iter().any(|x| path.starts_with(x))
+    }
+}
+
+impl Fairing for Cors {
+    fn info(&self) -> Info {
+        Info {
+            name: "CORS",
+            kind: Kind::Response,
+        }
+    }
+
+    fn on_response(&self, request: &Request, response: &mut Response) {
+        if !self.applies_to(request.uri().path()) {
+            return;
+        }
+
+        let allow_origin = match request
+            .headers()
+            .get_one("Origin")
+            .and_then(|x| self.allow_origin(x))
+        {
+            Some(x) => x,
+            None => return,
+        };
+
+        // There are no routes for OPTIONS requests, preflight requests are answered here.
+        let preflight = request.method() == Method::Options;
+        if preflight {
+            let method = match request.headers().get_one("Access-Control-Request-Method") {
+                Some(x) => x,
+                None => return,
+            };
+            if !self.allows_method(method) {
+                return;
+            }
+
+            response.set_status(Status::NoContent);
+            response.set_sized_body(Cursor::new(""));
+            response.remove_header("Content-Type");
+            response.set_raw_header("Access-Control-Allow-Methods", self.methods.join(", "));
+            response.set_raw_header("Access-Control-Allow-Headers", self.headers.join(", "));
+            response.set_raw_header("Access-Control-Max-Age", MAX_AGE.to_string());
+        } else {
+            response.set_raw_header("Access-Control-Expose-Headers", EXPOSE_HEADERS);
+        }
+
+        if allow_origin != "*" {
+            response.set_raw_header("Vary", "Origin");
+        }
+        response.set_raw_header("Access-Control-Allow-Origin", allow_origin);
+    }
+}
+
+fn split(s: &str) -> impl Iterator<Item = &str> {
+    s.split(',').map(str::trim).filter(|x| !x.is_empty())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_allow_origin() {
+        let cors = Cors::new(
+            "https://app.example.com/, https://pwa.example.com",
+            DEFAULT_METHODS,
+            DEFAULT_HEADERS,
+        );
+        assert_eq!(
+            cors.allow_origin("https://app.example.com").as_deref(),
+            Some("https://app.example.com")
+        );
+        assert_eq!(
+            cors.allow_origin("https://PWA.example.com").as_deref(),
+            Some("https://PWA.example.com")
+        );
+        assert_eq!(cors.allow_origin("https://evil.example.com"), None);
+        assert_eq!(cors.allow_origin("null"), None);
+
+        let cors = Cors::new("*", DEFAULT_METHODS, DEFAULT_HEADERS);
+        assert_eq!(cors.allow_origin("null").as_deref(), Some("*"));
+
+        let cors = Cors::new("", DEFAULT_METHODS, DEFAULT_HEADERS);
+        assert_eq!(cors.allow_origin("https://app.example.com"), None);
+    }
+
+    #[test]
+    fn test_methods_and_paths() {
+        let cors = Cors::new("*", "get, options", DEFAULT_HEADERS);
+        assert!(cors.allows_method("GET"));
+        assert!(!cors.allows_method("POST"));
+
+        assert!(cors.applies_to("/update/osx/1.0.0"));
+        assert!(cors.applies_to("/notes/1.0.0/1.1.0"));
+        assert!(cors.applies_to("/api/versions/osx"));
+        assert!(!cors.applies_to("/api/analytics/versions"));
+        assert!(!cors.applies_to("/download/app.zip"));
+    }
+}
@@ -0,0 +1,348 @@
+use crate::analytics::DownloadCount;
+use crate::backend::Release;
+use crate::cache::{self, Usage};
+use crate::catalogue::{self, Entry, Filter};
+use crate::policy::Policy;
+use crate::{Config, STABLE_CHANNEL};
+use failure::Error;
+use handlebars::Handlebars;
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::{HeaderMap, Method, Status};
+use rocket::request::{self, FromRequest};
+use rocket::{Outcome, Request, Response, State};
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// Name under which the dashboard template is registered.
+const TEMPLATE_NAME: &str = "dashboard";
+
+const TEMPLATE: &str = include_str!("../templates/dashboard.html.hbs");
+
+/// Path under which the dashboard is served.
+pub const PATH: &str = "/admin";
+
+/// Download stats on the dashboard cover this period, in seconds.
+pub const DOWNLOAD_PERIOD: i64 = 7 * 24 * 60 * 60;
+
+/// Renders the admin dashboard.
+pub struct Dashboard {
+    registry: Handlebars<'static>,
+}
+
+impl Dashboard {
+    pub fn new() -> Result<Self, Error> {
+        let mut registry = Handlebars::new();
+        registry.set_strict_mode(true);
+        registry.register_template_string(TEMPLATE_NAME, TEMPLATE)?;
+
+        Ok(Dashboard { registry })
+    }
+
+    pub fn render(&self, context: &Context) -> Result<String, Error> {
+        Ok(self.registry.render(TEMPLATE_NAME, context)?)
+    }
+}
+
+/// The data shown on the dashboard.
+#[derive(Debug, Default, Serialize)]
+pub struct Context {
+    /// The Github repository the releases come from.
+    pub app: String,
+    pub min_version: Option<String>,
+    pub channels: Vec<Channel>,
+    pub cache: Usage,
+    pub cache_size: String,
+    pub downloads: Vec<Downloads>,
+}
+
+/// The versions in a channel, from new to old.
+#[derive(Debug, Serialize)]
+pub struct Channel {
+    pub name: String,
+    pub versions: Vec<VersionRow>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct VersionRow {
+    pub version: String,
+    pub published_at: Option<String>,
+    pub rollout: u8,
+    pub yanked: bool,
+    pub critical: bool,
+    pub assets: Vec<Entry>,
+}
+
+/// The number of downloads of a version on a platform during the download period.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Downloads {
+    pub version: String,
+    pub platform: String,
+    pub downloads: u32,
+}
+
+impl Context {
+    /// Groups the releases per channel and version, the stable channel comes first. Releases are
+    /// expected to be sorted on version from new to old.
+    pub fn build(
+        app: &str,
+        releases: &[Box<dyn Release>],
+        policy: &Policy,
+        cache: Usage,
+        downloads: &[DownloadCount],
+    ) -> Self {
+        let mut channels: Vec<Channel> = vec![];
+        for entry in catalogue::entries(releases, &Filter::default(), policy) {
+            let index = match channels.iter().position(|x| x.name == entry.channel) {
+                Some(index) => index,
+                None => {
+                    channels.push(Channel {
+                        name: entry.channel.clone(),
+                        versions: vec![],
+                    });
+                    channels.len() - 1
+                }
+            };
+
+            let versions = &mut channels[index].versions;
+            match versions.last_mut() {
+                Some(last) if last.version == entry.version => last.assets.push(entry),
+                _ => versions.push(VersionRow {
+                    version: entry.version.clone(),
+                    published_at: entry.published_at.clone(),
+                    rollout: entry.rollout,
+                    yanked: entry.yanked,
+                    critical: entry.critical,
+                    assets: vec![entry],
+                }),
+            }
+        }
+        channels.sort_by_key(|x| x.name != STABLE_CHANNEL);
+
+        Context {
+            app: app.to_string(),
+            min_version: policy.min_version.as_ref().map(|x| x.to_string()),
+            channels,
+            cache_size: cache::format_bytes(cache.bytes),
+            cache,
+            downloads: total_downloads(downloads),
+        }
+    }
+}
+
+/// Sums the downloads per version and platform over all periods, most downloaded first.
+fn total_downloads(counts: &[DownloadCount]) -> Vec<Downloads> {
+    let mut totals: HashMap<(&str, &str), u32> = HashMap::new();
+    for x in counts {
+        *totals.entry((&x.version, &x.platform)).or_default() += x.downloads;
+    }
+
+    let mut out: Vec<Downloads> = totals
+        .into_iter()
+        .map(|((version, platform), downloads)| Downloads {
+            version: version.to_string(),
+            platform: platform.to_string(),
+            downloads,
+        })
+        .collect();
+    out.sort_by(|a, b| {
+        b.downloads
+            .cmp(&a.downloads)
+            .then_with(|| a.version.cmp(&b.version))
+            .then_with(|| a.platform.cmp(&b.platform))
+    });
+
+    out
+}
+
+/// SameOrigin is a rocket guard that only accepts requests that a browser reports as coming from
+/// the dashboard itself, which protects the dashboard forms against cross-site request forgery.
+/// The 'Origin' header is checked first, then 'Sec-Fetch-Site' and then 'Referer'. Requests
+/// without any of them are rejected. Origins are compared to the host of the base url, which is
+/// taken from the configuration or from the forwarding headers of trusted proxies.
+#[derive(Debug)]
+pub struct SameOrigin();
+
+impl FromRequest<'_, '_> for SameOrigin {
+    type Error = String;
+
+    fn from_request(request: &Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let config = request.guard::<State<Config>>().unwrap();
+        let host = match &config.base_url {
+            Some(base_url) => authority(base_url).map(str::to_string),
+            None => config
+                .trusted_proxies
+                .host(request.headers(), request.remote().map(|x| x.ip())),
+        };
+        if is_same_origin(request.headers(), host.as_deref()) {
+            return Outcome::Success(SameOrigin());
+        }
+
+        let origin = request.headers().get_one("Origin");
+        let referer = request.headers().get_one("Referer");
+        warn!(origin, referer; "rejected cross-origin dashboard request");
+        Outcome::Failure((Status::Forbidden, "Cross-origin request".to_string()))
+    }
+}
+
+/// Returns whether the browser reports the request as coming from a page on the given host.
+fn is_same_origin(headers: &HeaderMap, host: Option<&str>) -> bool {
+    let host = match host {
+        Some(x) => x,
+        None => return false,
+    };
+
+    if let Some(origin) = headers.get_one("Origin") {
+        return authority(origin).map_or(false, |x| x.eq_ignore_ascii_case(host));
+    }
+
+    if let Some(site) = headers.get_one("Sec-Fetch-Site") {
+        return site == "same-origin";
+    }
+
+    headers
+        .get_one("Referer")
+        .and_then(authority)
+        .map_or(false, |x| x.eq_ignore_ascii_case(host))
+}
+
+/// Returns the host and port of an url, e.g. 'example.com:8000' for
+/// 'https://example.com:8000/admin'.
+fn authority(url: &str) -> Option<&str> {
+    let (_, rest) = url.split_once("://")?;
+    let authority = rest.split(|c| c == '/' || c == '?' || c == '#').next()?;
+    Some(authority).filter(|x| !x.is_empty())
+}
+
+/// BasicAuthChallenge is a fairing that asks browsers for credentials when a dashboard page is
+/// requested without them, the API token is entered as the password.
+pub struct BasicAuthChallenge;
+
+impl Fairing for BasicAuthChallenge {
+    fn info(&self) -> Info {
+        Info {
+            name: "Dashboard basic auth challenge",
+            kind: Kind::Response,
+        }
+    }
+
+    fn on_response(&self, request: &Request, response: &mut Response) {
+        if response.status() == Status::Unauthorized
+            && request.method() == Method::Get
+            && request.uri().path().starts_with(PATH)
+        {
+            response.set_raw_header(
+                "WWW-Authenticate",
+                "Basic realm=\"nuts\", charset=\"UTF-8\"",
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backend::test_util::release;
+
+    fn count(version: &str, platform: &str, downloads: u32) -> DownloadCount {
+        DownloadCount {
+            period: "2026-10-19".to_string(),
+            version: version.to_string(),
+            platform: platform.to_string(),
+            downloads,
+        }
+    }
+
+    #[test]
+    fn test_build() {
+        let releases = vec![
+            release("app-1.3.0-mac-arm64.zip"),
+            release("app-1.3.0-win-x64.exe"),
+            release("app-1.2.0-mac-x64.zip"),
+        ];
+        let mut policy = Policy::default();
+        policy
+            .promotions
+            .insert("1.3.0".to_string(), "beta".to_string());
+
+        let context = Context::build(
+            "tacitic/app",
+            &releases,
+            &policy,
+            Usage::default(),
+            &[
+                count("1.2.0", "osx", 3),
+                count("1.3.0", "win", 1),
+                count("1.2.0", "osx", 2),
+            ],
+        );
+
+        assert_eq!(context.channels.len(), 2);
+        assert_eq!(context.channels[0].name, "stable");
+        assert_eq!(context.channels[0].versions[0].version, "1.2.0");
+        assert_eq!(context.channels[1].name, "beta");
+        assert_eq!(context.channels[1].versions[0].assets.len(), 2);
+        assert_eq!(
+            context.downloads[0],
+            Downloads {
+                version: "1.2.0".to_string(),
+                platform: "osx".to_string(),
+                downloads: 5
+            }
+        );
+
+        let html = Dashboard::new().unwrap().render(&context).unwrap();
+        assert!(html.contains("tacitic/app"));
+        assert!(html.contains("/admin/releases/1.3.0/yank"));
+    }
+
+    #[test]
+    fn test_is_same_origin() {
+        let headers = |list: &[(&'static str, &'static str)]| {
+            let mut headers = HeaderMap::new();
+            for (name, value) in list {
+                headers.add_raw(*name, *value);
+            }
+            headers
+        };
+        let host = ("Host", "nuts.example.com");
+        let same_origin = |headers: &HeaderMap| is_same_origin(headers, Some("nuts.example.com"));
+
+        assert!(same_origin(&headers(&[
+            host,
+            ("Origin", "https://nuts.example.com")
+        ])));
+        assert!(!same_origin(&headers(&[
+            host,
+            ("Origin", "https://evil.example.com"),
+            ("Sec-Fetch-Site", "same-origin")
+        ])));
+        assert!(!same_origin(&headers(&[host, ("Origin", "null")])));
+        assert!(same_origin(&headers(&[
+            host,
+            ("Sec-Fetch-Site", "same-origin")
+        ])));
+        assert!(!same_origin(&headers(&[
+            host,
+            ("Sec-Fetch-Site", "cross-site")
+        ])));
+        assert!(same_origin(&headers(&[
+            host,
+            ("Referer", "https://nuts.example.com/admin?tab=1")
+        ])));
+        assert!(!same_origin(&headers(&[
+            host,
+            ("Referer", "https://nuts.example.com.evil.com/admin")
+        ])));
+        assert!(!same_origin(&headers(&[host])));
+        assert!(!is_same_origin(
+            &headers(&[host, ("Origin", "https://nuts.example.com")]),
+            None
+        ));
+
+        // The host of the base url is used, not the 'Host' header a proxy sent.
+        assert!(same_origin(&headers(&[
+            ("Host", "nuts:8000"),
+            ("Origin", "https://nuts.example.com")
+        ])));
+    }
+}
@@ -0,0 +1,308 @@
+use crate::Version;
+use failure::Error;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::sync::RwLock;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long an answer of the entitlement hook is used for a licence key and version.
+pub const DEFAULT_CACHE_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// How long a request to the entitlement endpoint may take.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Asks whether a licence key covers a version, either with a POST request to an HTTP endpoint
+/// or by running a script. The request is sent as JSON in the body or on stdin, the answer is
+/// read as JSON from the response body or stdout.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Hook {
+    Http(String),
+    Script(PathBuf),
+}
+
+impl FromStr for Hook {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.is_empty() {
+            bail!("Empty entitlement hook");
+        }
+
+        if s.starts_with("http://") || s.starts_with("https://") {
+            return Ok(Hook::Http(s.to_string()));
+        }
+
+        Ok(Hook::Script(PathBuf::from(s)))
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct HookRequest<'a> {
+    license_key: &'a str,
+    version: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct HookResponse {
+    allowed: bool,
+    max_version: Option<String>,
+}
+
+impl Hook {
+    fn call(&self, key: &str, version: &Version) -> Result<Entitlement, Error> {
+        let request = HookRequest {
+            license_key: key,
+            version: version.to_string(),
+        };
+
+        let response: HookResponse = match self {
+            Hook::Http(url) => reqwest::Client::builder()
+                .timeout(REQUEST_TIMEOUT)
+                .build()?
+                .post(url)
+                .json(&request)
+                .send()?
+                .error_for_status()?
+                .json()?,
+            Hook::Script(path) => serde_json::from_slice(&run_script(
+                path,
+                &serde_json::to_vec(&request)?,
+                REQUEST_TIMEOUT,
+            )?)?,
+        };
+
+        Ok(Entitlement {
+            allowed: response.allowed,
+            max_version: match response.max_version {
+                Some(x) => Some(Version::from(&x)?),
+                None => None,
+            },
+        })
+    }
+}
+
+/// Runs a script with the input on stdin and returns its stdout. The script is killed when it
+/// takes longer than the timeout.
+fn run_script(path: &Path, input: &[u8], timeout: Duration) -> Result<Vec<u8>, Error> {
+    let mut child = Command::new(path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(input)?;
+    }
+
+    // Read on another thread, so a script that fills the pipe does not block the wait below.
+    let mut stdout = child.stdout.take().unwrap();
+    let reader = thread::spawn(move || {
+        let mut out = vec![];
+        stdout.read_to_end(&mut out).map(|_| out)
+    });
+
+    let started = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if started.elapsed() > timeout {
+            let _ = child.kill();
+            let _ = child.wait();
+            bail!("Entitlement script timed out after {:?}", timeout);
+        }
+        thread::sleep(Duration::from_millis(10));
+    };
+
+    if !status.success() {
+        bail!("Entitlement script exited with {}", status);
+    }
+    Ok(reader.join().unwrap()?)
+}
+
+/// The answer of the entitlement hook for a licence key.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entitlement {
+    pub allowed: bool,
+
+    /// The newest version the licence covers.
+    pub max_version: Option<Version>,
+}
+
+impl Entitlement {
+    /// Returns whether the licence covers a version.
+    pub fn covers(&self, version: &Version) -> bool {
+        self.allowed
+            && self
+                .max_version
+                .as_ref()
+                .map_or(true, |x| version.inner_version() <= x.inner_version())
+    }
+}
+
+/// Checks licence keys with the entitlement hook, answers are cached per licence key and version.
+#[derive(Debug, Default)]
+pub struct Entitlements {
+    hook: Option<Hook>,
+    ttl: Duration,
+    cache: RwLock<HashMap<(String, String), (Instant, Entitlement)>>,
+}
+
+impl Entitlements {
+    pub fn new(hook: Option<Hook>, ttl: Duration) -> Self {
+        Entitlements {
+            hook,
+            ttl,
+            cache: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Returns true when a hook is configured, licence keys are not checked otherwise.
+    pub fn is_enabled(&self) -> bool {
+        self.hook.is_some()
+    }
+
+    /// Returns the entitlement of a licence key for a version. Failures of the hook are not
+    /// cached, so the next request asks again.
+    pub fn check(&self, key: &str, version: &Version) -> Result<Entitlement, Error> {
+        let hook = match &self.hook {
+            Some(hook) => hook,
+            None => bail!("No entitlement hook configured"),
+        };
+
+        let cache_key = (key.to_string(), version.to_string());
+        if let Some((at, entitlement)) = self.cache.read().unwrap().get(&cache_key) {
+            if at.elapsed() < self.ttl {
+                return Ok(entitlement.clone());
+            }
+        }
+
+        let entitlement = hook.call(key, version)?;
+        let mut cache = self.cache.write().unwrap();
+        cache.retain(|_, (at, _)| at.elapsed() < self.ttl);
+        cache.insert(cache_key, (Instant::now(), entitlement.clone()));
+
+        Ok(entitlement)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_server;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    /// Starts a stand-in for the licensing service that answers every request with `body`, the
+    /// returned counter holds the number of requests it received.
+    fn serve(body: &'static str) -> (String, Arc<AtomicUsize>) {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        let url = test_server::serve(move |request| {
+            assert!(request.body.contains("\"license_key\""));
+            counter.fetch_add(1, Ordering::SeqCst);
+            (200, body.to_string())
+        });
+
+        (format!("{}/entitlement", url), requests)
+    }
+
+    #[test]
+    fn test_http_hook() {
+        let (url, requests) = serve(r#"{"allowed": true, "max_version": "1.4.0"}"#);
+        let entitlements = Entitlements::new(Some(url.parse().unwrap()), DEFAULT_CACHE_TTL);
+        assert!(entitlements.is_enabled());
+
+        let version = Version::from("1.2.0").unwrap();
+        let entitlement = entitlements.check("key-1", &version).unwrap();
+        assert_eq!(
+            entitlement.max_version,
+            Some(Version::from("1.4.0").unwrap())
+        );
+        assert!(entitlement.covers(&Version::from("1.4.0").unwrap()));
+        assert!(!entitlement.covers(&Version::from("1.5.0").unwrap()));
+
+        // The second check is answered from the cache.
+        assert_eq!(entitlements.check("key-1", &version).unwrap(), entitlement);
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        entitlements.check("key-2", &version).unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_http_hook_deny() {
+        let (url, _) = serve(r#"{"allowed": false}"#);
+        let entitlements = Entitlements::new(Some(Hook::Http(url)), Duration::from_secs(0));
+
+        let entitlement = entitlements
+            .check("expired", &Version::from("1.0.0").unwrap())
+            .unwrap();
+        assert!(!entitlement.allowed);
+        assert!(!entitlement.covers(&Version::from("0.1.0").unwrap()));
+    }
+
+    #[test]
+    fn test_script_hook() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("entitlement.sh");
+        std::fs::write(
+            &path,
+            "#!/bin/sh\ngrep -q '\"license_key\":\"valid\"' && echo '{\"allowed\": true}' \
+             || echo '{\"allowed\": false}'\n",
+        )
+        .unwrap();
+        let status = Command::new("chmod").arg("+x").arg(&path).status().unwrap();
+        assert!(status.success());
+
+        let entitlements = Entitlements::new(Some(Hook::Script(path)), DEFAULT_CACHE_TTL);
+        let version = Version::from("2.0.0").unwrap();
+        assert!(entitlements
+            .check("valid", &version)
+            .unwrap()
+            .covers(&version));
+        assert!(!entitlements.check("invalid", &version).unwrap().allowed);
+
+        let entitlements = Entitlements::new(
+            Some(Hook::Script(dir.path().join("missing.sh"))),
+            DEFAULT_CACHE_TTL,
+        );
+        assert!(entitlements.check("valid", &version).is_err());
+    }
+
+    #[test]
+    fn test_script_timeout() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("entitlement.sh");
+        std::fs::write(
+            &path,
+            "#!/bin/sh
+sleep 5
+echo '{\"allowed\": true}'\n",
+        )
+        .unwrap();
+        let status = Command::new("chmod").arg("+x").arg(&path).status().unwrap();
+        assert!(status.success());
+
+        let started = Instant::now();
+        assert!(run_script(&path, b"{}", Duration::from_millis(100)).is_err());
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_parse_hook() {
+        assert_eq!(
+            "https://licensing.local/check".parse::<Hook>().unwrap(),
+            Hook::Http("https://licensing.local/check".to_string())
+        );
+        assert_eq!(
+            "/usr/local/bin/check".parse::<Hook>().unwrap(),
+            Hook::Script(PathBuf::from("/usr/local/bin/check"))
+        );
+        assert!("".parse::<Hook>().is_err());
+    }
+}
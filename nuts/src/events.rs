@@ -0,0 +1,194 @@
+use crate::backend::Release;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+/// A release asset as it was in the release index.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Asset {
+    pub filename: String,
+    pub version: String,
+    pub platform: String,
+    pub arch: Option<String>,
+
+    /// The channel of the release on the backend, `None` for the stable channel.
+    pub channel: Option<String>,
+    pub notes: Option<String>,
+    pub rollout: Option<u8>,
+    pub critical: bool,
+    pub size: Option<u64>,
+    pub checksum: Option<String>,
+    pub published_at: Option<String>,
+}
+
+impl Asset {
+    pub fn new(release: &dyn Release) -> Self {
+        Asset {
+            filename: release.get_filename().to_string_lossy().to_string(),
+            version: release.get_version().to_string(),
+            platform: release.get_platform().to_string(),
+            arch: release.get_arch().map(|x| x.to_string()),
+            channel: release.get_channel().map(str::to_string),
+            notes: release.get_notes().map(str::to_string),
+            rollout: release.get_rollout(),
+            critical: release.is_critical(),
+            size: release.get_size(),
+            checksum: release.get_checksum().map(str::to_string),
+            published_at: release.get_published_at().map(str::to_string),
+        }
+    }
+}
+
+/// A change to the release index, found when a newly fetched index is compared to the previous
+/// one.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ReleaseEvent {
+    Added { asset: Asset },
+    Removed { asset: Asset },
+    Changed { old: Box<Asset>, new: Asset },
+}
+
+impl ReleaseEvent {
+    /// Returns the asset as it is now, or as it was before it was removed.
+    pub fn asset(&self) -> &Asset {
+        match self {
+            ReleaseEvent::Added { asset } | ReleaseEvent::Removed { asset } => asset,
+            ReleaseEvent::Changed { new, .. } => new,
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ReleaseEvent::Added { .. } => "added",
+            ReleaseEvent::Removed { .. } => "removed",
+            ReleaseEvent::Changed { .. } => "changed",
+        }
+    }
+}
+
+/// Compares two release indexes on filename. Removed assets come first, followed by the added and
+/// changed assets in the order of the new index.
+pub fn diff(old: &[Asset], new: &[Asset]) -> Vec<ReleaseEvent> {
+    let before: HashMap<&str, &Asset> = old.iter().map(|x| (x.filename.as_str(), x)).collect();
+    let after: HashMap<&str, &Asset> = new.iter().map(|x| (x.filename.as_str(), x)).collect();
+
+    let mut out: Vec<ReleaseEvent> = old
+        .iter()
+        .filter(|x| !after.contains_key(x.filename.as_str()))
+        .map(|x| ReleaseEvent::Removed { asset: x.clone() })
+        .collect();
+
+    for asset in new {
+        match before.get(asset.filename.as_str()) {
+            None => out.push(ReleaseEvent::Added {
+                asset: asset.clone(),
+            }),
+            Some(old) if *old != asset => out.push(ReleaseEvent::Changed {
+                old: Box::new((*old).clone()),
+                new: asset.clone(),
+            }),
+            Some(_) => {}
+        }
+    }
+
+    out
+}
+
+type Subscriber = Box<dyn Fn(&[ReleaseEvent]) + Send + Sync>;
+
+/// Passes release events to the features that subscribed to them. Subscribers receive all changes
+/// of a refresh at once, on the thread that refreshed the index, so slow work should be moved to a
+/// thread of its own.
+#[derive(Default)]
+pub struct Events {
+    subscribers: RwLock<Vec<Subscriber>>,
+}
+
+impl Events {
+    pub fn subscribe<F>(&self, f: F)
+    where
+        F: Fn(&[ReleaseEvent]) + Send + Sync + 'static,
+    {
+        self.subscribers.write().unwrap().push(Box::new(f));
+    }
+
+    /// Passes the changes of a refresh to the subscribers, nothing is passed without changes.
+    pub fn publish(&self, events: &[ReleaseEvent]) {
+        if events.is_empty() {
+            return;
+        }
+
+        for subscriber in self.subscribers.read().unwrap().iter() {
+            subscriber(events);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    fn asset(filename: &str) -> Asset {
+        Asset {
+            filename: filename.to_string(),
+            version: filename.split('-').nth(1).unwrap().to_string(),
+            platform: "osx".to_string(),
+            arch: None,
+            channel: None,
+            notes: None,
+            rollout: None,
+            critical: false,
+            size: Some(100),
+            checksum: None,
+            published_at: None,
+        }
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = vec![asset("app-1.1.0-mac.zip"), asset("app-1.0.0-mac.zip")];
+        let mut changed = asset("app-1.1.0-mac.zip");
+        changed.rollout = Some(50);
+        let new = vec![asset("app-1.2.0-mac.zip"), changed.clone()];
+
+        assert_eq!(
+            diff(&old, &new),
+            vec![
+                ReleaseEvent::Removed {
+                    asset: asset("app-1.0.0-mac.zip")
+                },
+                ReleaseEvent::Added {
+                    asset: asset("app-1.2.0-mac.zip")
+                },
+                ReleaseEvent::Changed {
+                    old: Box::new(asset("app-1.1.0-mac.zip")),
+                    new: changed
+                },
+            ]
+        );
+        assert!(diff(&new, &new).is_empty());
+    }
+
+    #[test]
+    fn test_publish() {
+        let events = Events::default();
+        let seen = Arc::new(Mutex::new(vec![]));
+        for _ in 0..2 {
+            let seen = seen.clone();
+            events.subscribe(move |x| seen.lock().unwrap().push(x.len()));
+        }
+
+        events.publish(&[]);
+        events.publish(&diff(
+            &[],
+            &[asset("app-1.2.0-mac.zip"), asset("app-1.2.0-win.exe")],
+        ));
+        assert_eq!(*seen.lock().unwrap(), vec![2, 2]);
+
+        let json = serde_json::to_value(&diff(&[asset("app-1.2.0-mac.zip")], &[])[0]).unwrap();
+        assert_eq!(json["kind"], "removed");
+        assert_eq!(json["asset"]["version"], "1.2.0");
+    }
+}
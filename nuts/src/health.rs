@@ -0,0 +1,102 @@
+use crate::backend::SyncStatus;
+use serde::Serialize;
+use std::path::Path;
+use std::time::Duration;
+use tempfile::NamedTempFile;
+
+/// How long ago the last successful sync may be before nuts is no longer ready.
+pub const DEFAULT_MAX_SYNC_AGE: Duration = Duration::from_secs(10 * 60);
+
+/// The outcome of a readiness check, nuts is ready when all checks pass.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Readiness {
+    pub ready: bool,
+
+    /// A release index has been loaded from the backend.
+    pub index_loaded: bool,
+
+    /// The last sync succeeded, and not longer ago than the maximum sync age.
+    pub backend_reachable: bool,
+
+    /// Release assets can be stored in the cache directory.
+    pub cache_writable: bool,
+
+    /// Seconds since the last successful sync.
+    pub last_sync_age: Option<i64>,
+
+    /// The error of the last sync, if it failed.
+    pub backend_error: Option<String>,
+
+    /// The number of release assets in the index.
+    pub releases: usize,
+}
+
+impl Readiness {
+    pub fn check(status: SyncStatus, cache_dir: &Path, max_sync_age: Duration, now: i64) -> Self {
+        let last_sync_age = status.last_success.map(|x| now - x);
+        let index_loaded = status.last_success.is_some();
+        let backend_reachable = status.last_error.is_none()
+            && last_sync_age.map_or(false, |x| x <= max_sync_age.as_secs() as i64);
+        let cache_writable = is_writable(cache_dir);
+
+        Readiness {
+            ready: index_loaded && backend_reachable && cache_writable,
+            index_loaded,
+            backend_reachable,
+            cache_writable,
+            last_sync_age,
+            backend_error: status.last_error,
+            releases: status.releases,
+        }
+    }
+}
+
+/// Returns whether a file can be created in the given directory.
+pub fn is_writable(dir: &Path) -> bool {
+    NamedTempFile::new_in(dir).is_ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_readiness() {
+        let dir = tempfile::tempdir().unwrap();
+        let max_age = Duration::from_secs(60);
+        let synced = SyncStatus {
+            last_success: Some(1000),
+            last_error: None,
+            releases: 4,
+        };
+
+        let readiness = Readiness::check(synced.clone(), dir.path(), max_age, 1030);
+        assert!(readiness.ready);
+        assert_eq!(readiness.last_sync_age, Some(30));
+
+        let readiness = Readiness::check(synced.clone(), dir.path(), max_age, 1100);
+        assert!(!readiness.ready);
+        assert!(readiness.index_loaded);
+        assert!(!readiness.backend_reachable);
+
+        let failed = SyncStatus {
+            last_error: Some("Unexpected status code: 401".to_string()),
+            ..synced.clone()
+        };
+        let readiness = Readiness::check(failed, dir.path(), max_age, 1030);
+        assert!(!readiness.ready);
+        assert_eq!(
+            readiness.backend_error.as_deref(),
+            Some("Unexpected status code: 401")
+        );
+
+        let readiness = Readiness::check(SyncStatus::default(), dir.path(), max_age, 1030);
+        assert!(!readiness.index_loaded);
+
+        let missing = PathBuf::from("/nonexistent/nuts-cache");
+        let readiness = Readiness::check(synced, &missing, max_age, 1030);
+        assert!(!readiness.cache_writable);
+        assert!(!readiness.ready);
+    }
+}
@@ -0,0 +1,372 @@
+use crate::backend::{self, Release};
+use crate::notes::{self, Format};
+use crate::policy::Policy;
+use crate::{Arch, Client, PackageType, Platform};
+use failure::Error;
+use handlebars::Handlebars;
+use rocket::http::uri::Uri;
+use rocket::request::{self, FromRequest};
+use rocket::{Outcome, Request};
+use serde::Serialize;
+use std::fs;
+
+/// Name under which the landing page template is registered.
+const TEMPLATE_NAME: &str = "index";
+
+/// The landing page template that is used when no custom template is configured.
+const DEFAULT_TEMPLATE: &str = include_str!("../templates/index.html.hbs");
+
+/// Renders the landing page, the template can be replaced with a custom handlebars template.
+pub struct LandingPage {
+    registry: Handlebars<'static>,
+}
+
+impl LandingPage {
+    /// Loads the template from the given path, or the default template when there is none.
+    pub fn new(path: Option<&str>) -> Result<Self, Error> {
+        let template = match path {
+            Some(path) => fs::read_to_string(path)?,
+            None => DEFAULT_TEMPLATE.to_string(),
+        };
+
+        let mut registry = Handlebars::new();
+        registry.register_template_string(TEMPLATE_NAME, template)?;
+
+        Ok(LandingPage { registry })
+    }
+
+    pub fn render(&self, context: &Context) -> Result<String, Error> {
+        Ok(self.registry.render(TEMPLATE_NAME, context)?)
+    }
+}
+
+/// The data that is available to the landing page template.
+#[derive(Debug, Default, Serialize)]
+pub struct Context {
+    /// The latest version for the platform of the visitor, or any platform when it is unknown.
+    pub version: Option<String>,
+
+    /// Release notes of the latest version as html.
+    pub notes: Option<String>,
+
+    /// The download for the platform of the visitor.
+    pub recommended: Option<Download>,
+
+    /// Downloads for all other platforms.
+    pub downloads: Vec<Download>,
+}
+
+impl Context {
+    /// Collects the latest downloads per platform from the releases. The download matching the
+    /// platform of the visitor is recommended, its version and notes are shown on the page.
+    pub fn build(
+        releases: &[Box<dyn Release>],
+        visitor: &Visitor,
+        client: &Client,
+        policy: &Policy,
+        base_url: &str,
+    ) -> Self {
+        let mut out = Context::default();
+        let query = client_query(client);
+
+        for platform in &[Platform::MacOS, Platform::Windows, Platform::Linux] {
+            let package_types = PackageType::for_download(*platform);
+            let release = match visitor.latest(releases, *platform, package_types, client, policy) {
+                Some(release) => release,
+                None => continue,
+            };
+
+            // The link resolves the architecture of the visitor in the same way, and the channel
+            // and id of the client are passed on so it resolves the same release.
+            let url = format!(
+                "{}/download/latest/{}{}",
+                base_url,
+                platform.to_string(),
+                query
+            );
+
+            let download = Download {
+                platform: platform.to_string(),
+                name: platform_name(*platform).to_string(),
+                version: release.get_version().to_string(),
+                filename: release.get_filename().to_string_lossy().to_string(),
+                url,
+            };
+
+            if out.version.is_none() || visitor.platform == Some(*platform) {
+                out.version = Some(download.version.clone());
+                out.notes = release.get_notes().map(|x| notes::render(x, Format::Html));
+            }
+
+            if visitor.platform == Some(*platform) {
+                out.recommended = Some(download);
+            } else {
+                out.downloads.push(download);
+            }
+        }
+
+        out
+    }
+}
+
+/// Returns the query string with the channel and id of a client, empty when it has neither.
+fn client_query(client: &Client) -> String {
+    let params: Vec<String> = [("channel", &client.channel), ("id", &client.id)]
+        .iter()
+        .filter_map(|(name, value)| {
+            value
+                .as_ref()
+                .map(|x| format!("{}={}", name, Uri::percent_encode(x)))
+        })
+        .collect();
+
+    if params.is_empty() {
+        return String::new();
+    }
+    format!("?{}", params.join("&"))
+}
+
+/// A download link as shown on the landing page.
+#[derive(Debug, Serialize)]
+pub struct Download {
+    pub platform: String,
+    pub name: String,
+    pub version: String,
+    pub filename: String,
+    pub url: String,
+}
+
+/// Returns a human readable name for a platform.
+pub fn platform_name(platform: Platform) -> &'static str {
+    match platform {
+        Platform::MacOS => "macOS",
+        Platform::Windows => "Windows",
+        Platform::Linux => "Linux",
+    }
+}
+
+/// Visitor is a rocket guard that detects the platform and architecture of a visitor from the
+/// 'User-Agent' header.
+#[derive(Debug, Default, PartialEq)]
+pub struct Visitor {
+    pub platform: Option<Platform>,
+    pub arch: Option<Arch>,
+}
+
+impl Visitor {
+    /// Detects the platform and architecture from a user-agent.
+    pub fn from_user_agent(user_agent: &str) -> Self {
+        let ua = user_agent.to_lowercase();
+
+        let platform = if ua.contains("android") || ua.contains("iphone") || ua.contains("ipad") {
+            None
+        } else if ua.contains("macintosh") || ua.contains("mac os x") {
+            Some(Platform::MacOS)
+        } else if ua.contains("windows") {
+            Some(Platform::Windows)
+        } else if ua.contains("linux") || ua.contains("x11") {
+            Some(Platform::Linux)
+        } else {
+            None
+        };
+
+        // Browsers on macOS report an Intel cpu regardless of the actual architecture.
+        let arch = match platform {
+            Some(Platform::MacOS) | None => None,
+            Some(_) if ua.contains("aarch64") || ua.contains("arm64") => Some(Arch::Arm64),
+            Some(_) if ua.contains("armv7l") => Some(Arch::Armv7l),
+            Some(_)
+                if ua.contains("x86_64")
+                    || ua.contains("x64")
+                    || ua.contains("win64")
+                    || ua.contains("wow64")
+                    || ua.contains("amd64") =>
+            {
+                Some(Arch::X64)
+            }
+            Some(_) if ua.contains("i686") || ua.contains("i386") => Some(Arch::Ia32),
+            Some(_) => None,
+        };
+
+        Visitor { platform, arch }
+    }
+
+    /// Returns the architecture of the visitor when it is on the given platform.
+    pub fn arch_for(&self, platform: Platform) -> Option<Arch> {
+        self.arch.filter(|_| self.platform == Some(platform))
+    }
+
+    /// Returns the newest of the releases for a platform in the architecture of the visitor.
+    /// Falls back to the default architecture when the architecture of the visitor has no
+    /// releases, as x64 builds run emulated on arm64 Macs and Windows.
+    pub fn latest<'a>(
+        &self,
+        releases: &'a [Box<dyn Release>],
+        platform: Platform,
+        package_types: &[PackageType],
+        client: &Client,
+        policy: &Policy,
+    ) -> Option<&'a dyn Release> {
+        let arch = self.arch_for(platform);
+        backend::latest(releases, platform, arch, package_types, client, policy).or_else(|| {
+            arch.and_then(|_| {
+                backend::latest(releases, platform, None, package_types, client, policy)
+            })
+        })
+    }
+}
+
+impl FromRequest<'_, '_> for Visitor {
+    type Error = String;
+
+    fn from_request(request: &Request<'_>) -> request::Outcome<Self, Self::Error> {
+        Outcome::Success(
+            request
+                .headers()
+                .get_one("User-Agent")
+                .map(Visitor::from_user_agent)
+                .unwrap_or_default(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backend::test_util::{release, TestRelease};
+
+    #[test]
+    fn test_visitor_from_user_agent() {
+        let tests = vec![
+            (
+                "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/605.1.15",
+                Some(Platform::MacOS),
+                None,
+            ),
+            (
+                "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36",
+                Some(Platform::Windows),
+                Some(Arch::X64),
+            ),
+            (
+                "Mozilla/5.0 (X11; Linux aarch64; rv:109.0) Gecko/20100101 Firefox/115.0",
+                Some(Platform::Linux),
+                Some(Arch::Arm64),
+            ),
+            (
+                "Mozilla/5.0 (Linux; Android 13; Pixel 7) AppleWebKit/537.36",
+                None,
+                None,
+            ),
+            ("curl/8.0.1", None, None),
+        ];
+
+        for (user_agent, platform, arch) in tests {
+            assert_eq!(
+                Visitor::from_user_agent(user_agent),
+                Visitor { platform, arch },
+                "{}",
+                user_agent
+            );
+        }
+    }
+
+    #[test]
+    fn test_visitor_latest() {
+        let releases = vec![
+            release("app-1.3.0-win-x64.exe"),
+            release("app-1.3.0-mac-arm64.dmg"),
+        ];
+        let client = Client::default();
+        let policy = Policy::default();
+        let latest = |visitor: &Visitor, platform| {
+            let package_types = PackageType::for_download(platform);
+            visitor
+                .latest(&releases, platform, package_types, &client, &policy)
+                .map(|x| x.get_filename().to_string_lossy().to_string())
+        };
+
+        // Windows on arm64 falls back to x64 builds.
+        let visitor = Visitor {
+            platform: Some(Platform::Windows),
+            arch: Some(Arch::Arm64),
+        };
+        assert_eq!(
+            latest(&visitor, Platform::Windows).unwrap(),
+            "app-1.3.0-win-x64.exe"
+        );
+        assert_eq!(latest(&visitor, Platform::MacOS), None);
+    }
+
+    #[test]
+    fn test_build_context() {
+        let releases = vec![
+            TestRelease {
+                channel: Some("beta".to_string()),
+                ..TestRelease::new("app-1.4.0-win-x64.exe")
+            }
+            .boxed(),
+            release("app-1.3.0-win-x64.exe"),
+            release("app-1.3.0-mac.dmg"),
+        ];
+        let visitor = Visitor {
+            platform: Some(Platform::Windows),
+            arch: Some(Arch::X64),
+        };
+        let policy = Policy {
+            channels: vec!["beta".to_string()],
+            ..Policy::default()
+        };
+
+        let context = Context::build(
+            &releases,
+            &visitor,
+            &Client::default(),
+            &policy,
+            "https://nuts.local",
+        );
+        let recommended = context.recommended.unwrap();
+        assert_eq!(recommended.version, "1.3.0");
+        assert_eq!(recommended.url, "https://nuts.local/download/latest/win");
+
+        // A beta visitor is linked to the beta download it is shown.
+        let client = Client {
+            id: Some("client a".to_string()),
+            channel: Some("beta".to_string()),
+            ..Client::default()
+        };
+        let context = Context::build(&releases, &visitor, &client, &policy, "https://nuts.local");
+        let recommended = context.recommended.unwrap();
+        assert_eq!(recommended.version, "1.4.0");
+        assert_eq!(
+            recommended.url,
+            "https://nuts.local/download/latest/win?channel=beta&id=client%20a"
+        );
+        assert_eq!(
+            context.downloads[0].url,
+            "https://nuts.local/download/latest/osx?channel=beta&id=client%20a"
+        );
+    }
+
+    #[test]
+    fn test_render_default_template() {
+        let page = LandingPage::new(None).unwrap();
+        let html = page
+            .render(&Context {
+                version: Some("1.2.0".to_string()),
+                notes: Some("<p>Fixes</p>".to_string()),
+                recommended: Some(Download {
+                    platform: "osx".to_string(),
+                    name: "macOS".to_string(),
+                    version: "1.2.0".to_string(),
+                    filename: "app-1.2.0.dmg".to_string(),
+                    url: "/download/latest/osx".to_string(),
+                }),
+                downloads: vec![],
+            })
+            .unwrap();
+
+        assert!(html.contains("href=\"/download/latest/osx\""));
+        assert!(html.contains("<p>Fixes</p>"));
+    }
+}
@@ -1,22 +1,58 @@
 #![feature(proc_macro_hygiene, decl_macro)]
 
 use rocket::http::{RawStr, Status};
-use rocket::request::{self, FromParam, FromRequest};
+use rocket::request::{self, FromFormValue, FromParam, FromRequest};
 use rocket::{Outcome, Request, State};
 
+pub mod analytics;
+pub mod audit;
+pub mod auth;
 pub mod backend;
+pub mod cache;
+pub mod catalogue;
+pub mod clock;
+pub mod cors;
+pub mod dashboard;
+pub mod entitlement;
+#[allow(dead_code)]
 pub(crate) mod error;
-pub(crate) use error::ErrorKind;
+pub mod events;
+pub mod health;
+pub mod jwt;
+pub mod landing;
+pub mod logging;
+pub mod metrics;
+pub mod notes;
+pub mod overrides;
+pub mod policy;
+pub mod proxy;
+pub mod rate_limit;
+#[cfg(test)]
+mod test_server;
+pub mod webhook;
+use audit::{AuditLog, Entry};
+use auth::{AuthError, Identity, Scope, Token, Tokens};
+use entitlement::Entitlements;
+pub use error::ErrorKind;
+use jwt::{Claims, JwtValidator};
+use policy::Policy;
+use proxy::TrustedProxies;
+use rate_limit::{RateLimiter, RetryAfter};
 use signed_urls::validate;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
 
 #[macro_use]
 extern crate failure;
 
 #[macro_use]
-extern crate rocket;
+extern crate lazy_static;
+
+#[macro_use]
+extern crate log;
 
 /// Represents a platform
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Platform {
     MacOS,
     Windows,
@@ -64,11 +100,128 @@ impl ToString for Platform {
     }
 }
 
+/// Represents a cpu architecture
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Arch {
+    X64,
+    Ia32,
+    Arm64,
+    Armv7l,
+}
+
+impl Arch {
+    /// Detects an architecture from a given filename, returns `None` when the filename does not
+    /// mention one.
+    pub fn detect_from_filename(name: &str) -> Option<Self> {
+        let name = name.to_lowercase();
+
+        if name.contains("arm64") || name.contains("aarch64") {
+            return Some(Self::Arm64);
+        }
+
+        if name.contains("armv7l") || name.contains("armhf") {
+            return Some(Self::Armv7l);
+        }
+
+        if name.contains("x64") || name.contains("x86_64") || name.contains("amd64") {
+            return Some(Self::X64);
+        }
+
+        if name.contains("ia32") || name.contains("i386") || name.contains("i686") {
+            return Some(Self::Ia32);
+        }
+
+        None
+    }
+}
+
+impl ToString for Arch {
+    fn to_string(&self) -> String {
+        match &self {
+            Arch::X64 => "x64".to_string(),
+            Arch::Ia32 => "ia32".to_string(),
+            Arch::Arm64 => "arm64".to_string(),
+            Arch::Armv7l => "armv7l".to_string(),
+        }
+    }
+}
+
+/// Represents the type of package a release asset is distributed as.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PackageType {
+    Dmg,
+    Zip,
+    Exe,
+    Msi,
+    Deb,
+    Rpm,
+    AppImage,
+}
+
+impl ToString for PackageType {
+    fn to_string(&self) -> String {
+        match &self {
+            PackageType::Dmg => "dmg".to_string(),
+            PackageType::Zip => "zip".to_string(),
+            PackageType::Exe => "exe".to_string(),
+            PackageType::Msi => "msi".to_string(),
+            PackageType::Deb => "deb".to_string(),
+            PackageType::Rpm => "rpm".to_string(),
+            PackageType::AppImage => "appimage".to_string(),
+        }
+    }
+}
+
+impl PackageType {
+    /// Detects a package type from the extension of a given filename.
+    pub fn detect_from_filename(name: &str) -> Option<Self> {
+        let name = name.to_lowercase();
+        let types = [
+            (".dmg", Self::Dmg),
+            (".zip", Self::Zip),
+            (".exe", Self::Exe),
+            (".msi", Self::Msi),
+            (".deb", Self::Deb),
+            (".rpm", Self::Rpm),
+            (".appimage", Self::AppImage),
+        ];
+
+        types
+            .iter()
+            .find(|(ext, _)| name.ends_with(ext))
+            .map(|(_, package_type)| *package_type)
+    }
+
+    /// Returns the package type Squirrel expects for updates on a platform, any package is
+    /// served when there is none.
+    pub fn for_update(platform: Platform) -> Option<Self> {
+        match platform {
+            Platform::MacOS => Some(Self::Zip),
+            _ => None,
+        }
+    }
+
+    /// Returns the package types offered for downloads on a platform, ordered by preference.
+    pub fn for_download(platform: Platform) -> &'static [Self] {
+        match platform {
+            Platform::MacOS => &[Self::Dmg, Self::Zip],
+            Platform::Windows => &[Self::Exe, Self::Msi, Self::Zip],
+            Platform::Linux => &[Self::AppImage, Self::Deb, Self::Rpm],
+        }
+    }
+}
+
 /// Configuation for Nuts
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct Config {
-    /// Used to control access to the /update endpoint, only enforced when set.
-    pub secret_token: Option<String>,
+    /// Used to control access to the API endpoints, only enforced when there are any.
+    pub tokens: Tokens,
+
+    /// Validates JWTs that can be used instead of a token with the update scope.
+    pub jwt: JwtValidator,
+
+    /// Checks whether the licence key of a client covers the requested version.
+    pub entitlements: Entitlements,
 
     /// Used to control access to the /download endpoint, ony enforced when set.
     pub url_signature_secret: Option<String>,
@@ -81,33 +234,283 @@ pub struct Config {
 
     /// Will be used to generate the download urls, if not set hostname and scheme is used.
     pub base_url: Option<String>,
+
+    /// Directory in which downloaded release assets are cached.
+    pub cache_dir: PathBuf,
+
+    /// How long ago the last successful release sync may be for nuts to be ready.
+    pub max_sync_age: Duration,
+
+    /// Proxies whose forwarding headers are used to determine the host, scheme and client ip.
+    pub trusted_proxies: TrustedProxies,
+
+    /// Limits the number of requests per client on the update and download endpoints.
+    pub rate_limiter: RateLimiter,
+
+    /// Records administrative actions and rejected requests.
+    pub audit: AuditLog,
 }
 
-/// ApiToken is a rocket guard  that is used in combination with the 'secret_token' config parameter.
+/// ApiToken is a rocket guard that requires a bearer token with the update scope, it is only
+/// enforced when tokens are configured.
 #[derive(Debug)]
-pub struct ApiToken();
+pub struct ApiToken(pub Option<Token>);
+
+impl ApiToken {
+    /// Returns whether the token may request releases from a channel.
+    pub fn allows_channel(&self, channel: Option<&str>) -> bool {
+        self.0.as_ref().map_or(true, |x| x.allows_channel(channel))
+    }
+}
 
 impl FromRequest<'_, '_> for ApiToken {
     type Error = String;
 
+    fn from_request(request: &Request<'_>) -> request::Outcome<Self, Self::Error> {
+        authenticate(request, Scope::Update).map(ApiToken)
+    }
+}
+
+/// UpdateAuth is a rocket guard for the update endpoint that requires a bearer token with the
+/// update scope or a valid JWT, it is only enforced when tokens or JWT keys are configured.
+#[derive(Debug, Default)]
+pub struct UpdateAuth {
+    pub token: Option<Token>,
+    pub claims: Option<Claims>,
+}
+
+impl UpdateAuth {
+    /// Returns whether the token may request releases from a channel.
+    pub fn allows_channel(&self, channel: Option<&str>) -> bool {
+        self.token
+            .as_ref()
+            .map_or(true, |x| x.allows_channel(channel))
+    }
+
+    /// Applies the restrictions of the JWT claims to a client on the given version. A client on
+    /// a less stable channel than the claim allows is moved to the channel of the claim, a claim
+    /// never moves a client to a less stable channel.
+    pub fn restrict(&self, mut client: Client, version: &Version, policy: &Policy) -> Client {
+        let claims = match &self.claims {
+            Some(claims) => claims,
+            None => return client,
+        };
+
+        if let Some(allowed) = &claims.channel {
+            let allowed = Some(allowed.as_str()).filter(|x| *x != STABLE_CHANNEL);
+            let current = client.channel_for(version);
+            let narrows = allowed.is_none() || policy.accepts_channel(current.as_deref(), allowed);
+            if !policy.accepts_channel(allowed, current.as_deref()) && narrows {
+                client.channel = Some(allowed.unwrap_or(STABLE_CHANNEL).to_string());
+            }
+        }
+        client.max_version = claims.max_version.clone();
+
+        client
+    }
+}
+
+impl FromRequest<'_, '_> for UpdateAuth {
+    type Error = String;
+
     fn from_request(request: &Request<'_>) -> request::Outcome<Self, Self::Error> {
         let config = request.guard::<State<Config>>().unwrap();
-        if let Some(secret) = &config.secret_token {
-            let keys: Vec<_> = request.headers().get("Authorization").collect();
-            return match keys.len() {
-                0 => Outcome::Failure((Status::BadRequest, "Missing bearer token".to_string())),
-                1 if is_valid_auth_token(keys[0], secret) => Outcome::Success(ApiToken()),
-                1 => Outcome::Failure((Status::Unauthorized, "Unauthorized".to_string())),
-                _ => Outcome::Failure((Status::Unauthorized, "Unauthorized".to_string())),
+        if config.jwt.is_enabled() {
+            let secret = request
+                .headers()
+                .get_one("Authorization")
+                .and_then(auth::bearer_token);
+
+            match secret {
+                Some(secret) if jwt::is_jwt(secret) => {
+                    return match config.jwt.validate(secret) {
+                        Ok(claims) => {
+                            let name = format!("jwt:{}", claims.sub.as_deref().unwrap_or_default());
+                            identify(request, Some(name));
+                            Outcome::Success(UpdateAuth {
+                                token: None,
+                                claims: Some(claims),
+                            })
+                        }
+                        Err(e) => {
+                            warn!(error:% = e; "rejected jwt");
+                            audit_rejection(request, &config, None, &e.to_string());
+                            Outcome::Failure((Status::Unauthorized, "Invalid JWT".to_string()))
+                        }
+                    };
+                }
+                None if config.tokens.is_empty() => {
+                    return Outcome::Failure((
+                        Status::BadRequest,
+                        "Missing bearer token".to_string(),
+                    ));
+                }
+                Some(_) if config.tokens.is_empty() => {
+                    audit_rejection(request, &config, None, "Invalid JWT");
+                    return Outcome::Failure((Status::Unauthorized, "Unauthorized".to_string()));
+                }
+                _ => {}
+            }
+        }
+
+        authenticate(request, Scope::Update).map(|token| UpdateAuth {
+            token,
+            claims: None,
+        })
+    }
+}
+
+/// AdminToken is a rocket guard that requires a bearer token with the admin scope, it is only
+/// enforced when tokens are configured.
+#[derive(Debug)]
+pub struct AdminToken(pub Option<Token>);
+
+impl FromRequest<'_, '_> for AdminToken {
+    type Error = String;
+
+    fn from_request(request: &Request<'_>) -> request::Outcome<Self, Self::Error> {
+        authenticate(request, Scope::Admin).map(AdminToken)
+    }
+}
+
+/// DashboardToken is a rocket guard for the admin dashboard that requires a token with the admin
+/// scope, which browsers send with basic auth. It is only enforced when tokens are configured.
+#[derive(Debug)]
+pub struct DashboardToken(pub Option<Token>);
+
+impl FromRequest<'_, '_> for DashboardToken {
+    type Error = String;
+
+    fn from_request(request: &Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let config = request.guard::<State<Config>>().unwrap();
+        // Browsers only ask for credentials on a 401.
+        if !config.tokens.is_empty() && !request.headers().contains("Authorization") {
+            return Outcome::Failure((Status::Unauthorized, "Missing credentials".to_string()));
+        }
+
+        authenticate(request, Scope::Admin).map(DashboardToken)
+    }
+}
+
+/// Authenticates a request with the bearer token (or basic auth password) in the 'Authorization'
+/// header, the name of the token is kept in the request-local cache for logging.
+fn authenticate(request: &Request, scope: Scope) -> request::Outcome<Option<Token>, String> {
+    let config = request.guard::<State<Config>>().unwrap();
+    if config.tokens.is_empty() {
+        identify(request, None);
+        return Outcome::Success(None);
+    }
+
+    let headers: Vec<_> = request.headers().get("Authorization").collect();
+    let secret = match headers.as_slice() {
+        [] => return Outcome::Failure((Status::BadRequest, "Missing bearer token".to_string())),
+        [header] => auth::bearer_token(header)
+            .map(str::to_string)
+            .or_else(|| auth::basic_password(header)),
+        _ => None,
+    };
+
+    let result = match secret {
+        Some(secret) => {
+            config
+                .tokens
+                .authenticate(&secret, scope, &config.github_repository, clock::now())
+        }
+        None => Err(AuthError::UnknownToken),
+    };
+
+    match result {
+        Ok(token) => {
+            identify(request, Some(token.name.clone()));
+            Outcome::Success(Some(token.clone()))
+        }
+        Err(e) => {
+            warn!(scope:? = scope, error:% = e; "rejected api token");
+            let (status, name) = match &e {
+                AuthError::Forbidden(name) => (Status::Forbidden, Some(name.as_str())),
+                AuthError::Expired(name) => (Status::Unauthorized, Some(name.as_str())),
+                AuthError::UnknownToken => (Status::Unauthorized, None),
             };
+            audit_rejection(request, &config, name, &e.to_string());
+            Outcome::Failure((status, e.to_string()))
         }
+    }
+}
 
-        Outcome::Success(ApiToken())
+/// Marks a request as authenticated by a token guard, with the name of the token or JWT subject
+/// when access is restricted. Kept in the request-local cache for the audit log.
+struct Authenticated(Option<String>);
+
+/// Keeps the name of the token a request was authenticated with in the request-local cache.
+fn identify(request: &Request, name: Option<String>) {
+    request.local_cache(|| Identity(name.clone()));
+    request.local_cache(|| Some(Authenticated(name)));
+}
+
+/// Returns the address of the client, forwarding headers are only honoured from trusted proxies.
+fn client_ip(request: &Request, config: &Config) -> Option<String> {
+    config
+        .trusted_proxies
+        .client_ip(request.headers(), request.remote().map(|x| x.ip()))
+        .map(|x| x.to_string())
+}
+
+/// Records a rejected token or signature in the audit log, repeated failures of a client are
+/// counted instead of recorded one by one.
+fn audit_rejection(request: &Request, config: &Config, actor: Option<&str>, detail: &str) {
+    config.audit.record_failure(
+        Entry::new("auth.failed")
+            .actor(actor)
+            .ip(client_ip(request, config))
+            .target(request.uri().path())
+            .detail(detail),
+    );
+}
+
+/// Audit is a rocket guard that records entries in the audit log on behalf of the client. It has
+/// to come after the token guard of a route, which identifies the client, the request fails
+/// otherwise.
+#[derive(Debug)]
+pub struct Audit<'r> {
+    log: &'r AuditLog,
+    actor: Option<String>,
+    ip: Option<String>,
+}
+
+impl Audit<'_> {
+    pub fn record(&self, entry: Entry) {
+        self.log
+            .record(entry.actor(self.actor.as_deref()).ip(self.ip.clone()));
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for Audit<'r> {
+    type Error = String;
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        let config = request.guard::<State<'r, Config>>().unwrap().inner();
+        let actor = match request.local_cache(|| None::<Authenticated>) {
+            Some(x) => x.0.clone(),
+            None => {
+                error!(path = request.uri().path(); "audit guard used before a token guard");
+                return Outcome::Failure((
+                    Status::InternalServerError,
+                    "Unauthenticated request".to_string(),
+                ));
+            }
+        };
+
+        Outcome::Success(Audit {
+            log: &config.audit,
+            actor,
+            ip: client_ip(request, config),
+        })
     }
 }
 
 /// Signature is a rocket guard that is used in combination with the 'url_signature_secret'
-/// configuration option.
+/// configuration option. A token with the download scope can be used instead of a signature,
+/// without the option downloads are not restricted.
 #[derive(Debug)]
 pub struct Signature();
 
@@ -123,18 +526,28 @@ impl FromRequest<'_, '_> for Signature {
             format!(
                 "{base_url}{uri}",
                 base_url = base_url.to_string(),
-                uri = request.uri().to_string(),
+                uri = request.uri(),
             )
             .as_str(),
             "//",
             "/",
         );
-        println!("{}", url);
 
         if let Some(secret) = &config.url_signature_secret {
-            return match validate(&secret, &url) {
+            return match validate(secret, &url) {
                 Ok(_) => Outcome::Success(Signature()),
-                Err(_) => Outcome::Failure((Status::Unauthorized, "Invalid signature".to_string())),
+                // A token with the download scope can be used instead of a signature.
+                Err(_) if request.headers().contains("Authorization") => {
+                    match authenticate(request, Scope::Download) {
+                        Outcome::Success(Some(_)) => Outcome::Success(Signature()),
+                        _ => Outcome::Failure((Status::Unauthorized, "Unauthorized".to_string())),
+                    }
+                }
+                Err(e) => {
+                    warn!(url = logging::redact_url(&url), error:% = e; "invalid signature");
+                    audit_rejection(request, &config, None, &e.to_string());
+                    Outcome::Failure((Status::Unauthorized, "Invalid signature".to_string()))
+                }
             };
         }
 
@@ -142,12 +555,160 @@ impl FromRequest<'_, '_> for Signature {
     }
 }
 
+/// LicenceKey is a rocket guard that reads the licence key of the client from the 'X-License-Key'
+/// header or the 'license_key' query parameter, it is only required when an entitlement hook is
+/// configured. Routes check the key against the version they serve with `covers`.
+#[derive(Debug, Default)]
+pub struct LicenceKey(Option<String>);
+
+impl LicenceKey {
+    /// Returns the licence key of the client, `None` when no entitlement hook is configured.
+    pub fn key(&self) -> Option<&str> {
+        self.0.as_deref()
+    }
+
+    /// Asks the entitlement hook whether the licence key covers a version.
+    pub fn covers(&self, entitlements: &Entitlements, version: &Version) -> Result<bool, Status> {
+        let key = match &self.0 {
+            Some(key) => key,
+            None => return Ok(true),
+        };
+
+        match entitlements.check(key, version) {
+            Ok(entitlement) if entitlement.covers(version) => Ok(true),
+            Ok(_) => {
+                warn!(version = version.to_string(); "licence key not entitled");
+                Ok(false)
+            }
+            Err(e) => {
+                error!(error:% = e; "entitlement hook failed");
+                Err(Status::ServiceUnavailable)
+            }
+        }
+    }
+}
+
+impl FromRequest<'_, '_> for LicenceKey {
+    type Error = String;
+
+    fn from_request(request: &Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let config = request.guard::<State<Config>>().unwrap();
+        if !config.entitlements.is_enabled() {
+            return Outcome::Success(LicenceKey(None));
+        }
+
+        let key = match request.get_query_value::<String>("license_key") {
+            Some(Ok(key)) => Some(key),
+            _ => request
+                .headers()
+                .get_one("X-License-Key")
+                .map(str::to_string),
+        };
+        match key.filter(|x| !x.is_empty()) {
+            Some(key) => Outcome::Success(LicenceKey(Some(key))),
+            None => Outcome::Failure((Status::Unauthorized, "Missing licence key".to_string())),
+        }
+    }
+}
+
+/// RateLimit is a rocket guard that limits the number of requests of a client to a route, the
+/// route is the first segment of the path. Clients are identified by the name of their API token
+/// or otherwise by their ip address.
+#[derive(Debug)]
+pub struct RateLimit();
+
+impl FromRequest<'_, '_> for RateLimit {
+    type Error = String;
+
+    fn from_request(request: &Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let config = request.guard::<State<Config>>().unwrap();
+        let route = request.uri().segments().next().unwrap_or_default();
+
+        let token = request
+            .headers()
+            .get_one("Authorization")
+            .and_then(auth::bearer_token)
+            .and_then(|x| config.tokens.find(x));
+        let client = match token {
+            Some(token) => format!("token:{}", token.name),
+            None => {
+                let ip = config
+                    .trusted_proxies
+                    .client_ip(request.headers(), request.remote().map(|x| x.ip()));
+                format!("ip:{}", ip.map_or("unknown".to_string(), |x| x.to_string()))
+            }
+        };
+
+        match config.rate_limiter.check(route, &client, Instant::now()) {
+            Ok(()) => Outcome::Success(RateLimit()),
+            Err(retry_after) => {
+                warn!(route, client; "rate limited");
+                // Rounded up, so a client that waits is not limited again.
+                let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+                request.local_cache(|| RetryAfter(Some(seconds)));
+                Outcome::Failure((Status::TooManyRequests, "Too many requests".to_string()))
+            }
+        }
+    }
+}
+
+/// Name of the channel of releases without a pre-release identifier.
+pub const STABLE_CHANNEL: &str = "stable";
+
+/// Client is a rocket guard that identifies the client requesting an update, the identifier is
+/// read from the 'id' query parameter or the 'X-Client-Id' header.
+#[derive(Debug, Default)]
+pub struct Client {
+    /// A stable identifier of the client, used for staged rollouts.
+    pub id: Option<String>,
+
+    /// Overrides the channel of the client, read from the 'channel' query parameter.
+    pub channel: Option<String>,
+
+    /// The newest version the client may be offered, set from the claims of its JWT.
+    pub max_version: Option<Version>,
+}
+
+impl Client {
+    /// Returns the channel of the client, which is the channel of its version unless it was
+    /// overridden. The stable channel is represented by `None`.
+    pub fn channel_for(&self, version: &Version) -> Option<String> {
+        match &self.channel {
+            Some(c) if c == STABLE_CHANNEL => None,
+            Some(c) => Some(c.clone()),
+            None => version.channel(),
+        }
+    }
+}
+
+impl FromRequest<'_, '_> for Client {
+    type Error = String;
+
+    fn from_request(request: &Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let id = match request.get_query_value::<String>("id") {
+            Some(Ok(id)) => Some(id),
+            _ => request.headers().get_one("X-Client-Id").map(str::to_string),
+        };
+
+        let channel = match request.get_query_value::<String>("channel") {
+            Some(Ok(channel)) => Some(channel.to_lowercase()),
+            _ => None,
+        };
+
+        Outcome::Success(Client {
+            id: id.filter(|x| !x.is_empty()),
+            channel: channel.filter(|x| !x.is_empty()),
+            max_version: None,
+        })
+    }
+}
+
 /// Returns the baseurl, this can be overwritten in the configuration.
 pub struct BaseUrl(String);
 
 impl ToString for BaseUrl {
     fn to_string(&self) -> String {
-        if self.0.ends_with("/") {
+        if self.0.ends_with('/') {
             return self.0[..self.0.len() - 1].to_string();
         }
         self.0.clone()
@@ -160,13 +721,13 @@ impl FromRequest<'_, '_> for BaseUrl {
     fn from_request(request: &Request<'_>) -> request::Outcome<Self, Self::Error> {
         let config = request.guard::<State<Config>>().unwrap();
         if let Some(base_url) = &config.base_url {
-            println!("returning base-url from config {}", base_url);
+            debug!(base_url; "using base url from config");
             return Outcome::Success(BaseUrl(base_url.clone()));
         }
 
-        let scheme = get_scheme(&request);
+        let scheme = get_scheme(request, &config.trusted_proxies);
 
-        let host = match get_host(&request) {
+        let host = match get_host(request, &config.trusted_proxies) {
             Some(host) => host,
             None => {
                 return Outcome::Failure((
@@ -177,7 +738,7 @@ impl FromRequest<'_, '_> for BaseUrl {
         };
 
         let url = format!("{}://{}", scheme.to_string(), host);
-        println!("returning base-url from request {}", url);
+        debug!(base_url = url; "using base url from request");
 
         Outcome::Success(BaseUrl(url))
     }
@@ -198,50 +759,31 @@ impl ToString for Scheme {
     }
 }
 
-/// Returns the host of the request.
-fn get_host(req: &Request) -> Option<String> {
-    if let Some(h) = req.headers().get_one("X-Forwarded-Host") {
-        return Some(h.to_string());
-    }
-
-    req.headers().get_one("Host").map(|x| x.to_string())
+/// Returns the host of the request, forwarding headers are only honoured from trusted proxies.
+fn get_host(req: &Request, proxies: &TrustedProxies) -> Option<String> {
+    proxies.host(req.headers(), req.remote().map(|x| x.ip()))
 }
 
-/// Returns the scheme of the request, respectful to the X-Forwarded-Proto header
-fn get_scheme(req: &Request) -> Scheme {
-    let raw = match req.headers().get_one("X-Forwarded-Proto") {
-        Some(h) => h.to_string(),
-        None => req
-            .headers()
-            .get_one("Scheme")
-            .unwrap_or("http")
-            .to_string(),
-    };
-
-    match raw.as_str() {
-        "https" => Scheme::Https,
+/// Returns the scheme of the request, forwarding headers are only honoured from trusted proxies.
+fn get_scheme(req: &Request, proxies: &TrustedProxies) -> Scheme {
+    match proxies
+        .proto(req.headers(), req.remote().map(|x| x.ip()))
+        .as_deref()
+    {
+        Some("https") => Scheme::Https,
         _ => Scheme::Http,
     }
 }
 
-// TODO(rharink): Implement better token authentication
-fn is_valid_auth_token(header: &str, secret: &str) -> bool {
-    if header.starts_with("Bearer") {
-        let bearer_token = &header[7..];
-        return bearer_token == secret;
-    }
-
-    return false;
-}
-
 // TODO: docs
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Version(semver::Version);
 
 impl Version {
-    fn from(s: &str) -> Result<Self, semver::SemVerError> {
-        let mut name = s.clone();
-        if name.starts_with("v") {
+    /// Parses a version, an optional 'v' prefix is ignored.
+    pub fn from(s: &str) -> Result<Self, semver::SemVerError> {
+        let mut name = s;
+        if name.starts_with('v') {
             name = &name[1..];
         }
 
@@ -285,10 +827,60 @@ impl<'a> FromParam<'a> for Platform {
             return Ok(Platform::MacOS);
         }
 
+        if platform.contains("win") {
+            return Ok(Platform::Windows);
+        }
+
+        if platform.contains("linux") {
+            return Ok(Platform::Linux);
+        }
+
         bail!("Unsupported platform")
     }
 }
 
+impl<'a> FromParam<'a> for Arch {
+    type Error = failure::Error;
+
+    fn from_param(param: &'a RawStr) -> Result<Self, Self::Error> {
+        match param.percent_decode()?.to_lowercase().as_str() {
+            "x64" | "x86_64" | "amd64" => Ok(Arch::X64),
+            "ia32" | "x86" | "i386" => Ok(Arch::Ia32),
+            "arm64" | "aarch64" => Ok(Arch::Arm64),
+            "armv7l" | "armhf" => Ok(Arch::Armv7l),
+            _ => bail!("Unsupported architecture"),
+        }
+    }
+}
+
+impl<'v> FromFormValue<'v> for Platform {
+    type Error = failure::Error;
+
+    fn from_form_value(value: &'v RawStr) -> Result<Self, Self::Error> {
+        Platform::from_param(value)
+    }
+}
+
+impl<'v> FromFormValue<'v> for Arch {
+    type Error = failure::Error;
+
+    fn from_form_value(value: &'v RawStr) -> Result<Self, Self::Error> {
+        Arch::from_param(value)
+    }
+}
+
+impl<'a> FromParam<'a> for PackageType {
+    type Error = failure::Error;
+
+    fn from_param(param: &'a RawStr) -> Result<Self, Self::Error> {
+        let name = format!(".{}", param.percent_decode()?);
+        match PackageType::detect_from_filename(&name) {
+            Some(package_type) => Ok(package_type),
+            None => bail!("Unsupported package type"),
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -296,6 +888,57 @@ mod test {
     #[test]
     fn test_from_param() {
         Platform::from_param(RawStr::from_str("darwin")).unwrap();
+        assert_eq!(
+            Platform::from_param(RawStr::from_str("win32")).unwrap(),
+            Platform::Windows
+        );
+        assert_eq!(
+            Arch::from_param(RawStr::from_str("x86_64")).unwrap(),
+            Arch::X64
+        );
+        assert_eq!(
+            PackageType::from_param(RawStr::from_str("AppImage")).unwrap(),
+            PackageType::AppImage
+        );
+        assert!(PackageType::from_param(RawStr::from_str("x64")).is_err());
+    }
+
+    #[test]
+    fn test_parse_arch_and_package_type_from_filename() {
+        let tests = vec![
+            (
+                "app-1.0.0-arm64.dmg",
+                Some(Arch::Arm64),
+                Some(PackageType::Dmg),
+            ),
+            ("app-1.0.0-mac.zip", None, Some(PackageType::Zip)),
+            (
+                "app_1.0.0_amd64.deb",
+                Some(Arch::X64),
+                Some(PackageType::Deb),
+            ),
+            (
+                "app-1.0.0-x86_64.AppImage",
+                Some(Arch::X64),
+                Some(PackageType::AppImage),
+            ),
+            (
+                "app-setup-1.0.0-ia32.exe",
+                Some(Arch::Ia32),
+                Some(PackageType::Exe),
+            ),
+            ("app-1.0.0.dmg.blockmap", None, None),
+        ];
+
+        for (filename, arch, package_type) in tests {
+            assert_eq!(Arch::detect_from_filename(filename), arch, "{}", filename);
+            assert_eq!(
+                PackageType::detect_from_filename(filename),
+                package_type,
+                "{}",
+                filename
+            );
+        }
     }
 
     #[test]
@@ -324,6 +967,95 @@ mod test {
         Version::from("v2.0.0-beta.1").unwrap();
     }
 
+    #[test]
+    fn test_restrict_channel() {
+        let token = |channel: &str| UpdateAuth {
+            token: None,
+            claims: Some(Claims {
+                channel: Some(channel.to_string()),
+                ..Claims::default()
+            }),
+        };
+        let client = |channel: Option<&str>| Client {
+            channel: channel.map(str::to_string),
+            ..Client::default()
+        };
+        let stable = Version::from("1.0.0").unwrap();
+        let ranked = Policy {
+            channels: policy::parse_channels("alpha, beta"),
+            ..Policy::default()
+        };
+
+        for policy in &[Policy::default(), ranked.clone()] {
+            // A beta claim does not move a stable client to beta.
+            let out = token("beta").restrict(client(None), &stable, policy);
+            assert_eq!(out.channel_for(&stable), None);
+
+            // A stable claim moves a beta client to stable.
+            let out = token("stable").restrict(client(Some("beta")), &stable, policy);
+            assert_eq!(out.channel_for(&stable), None);
+        }
+
+        let out = token("beta").restrict(client(Some("alpha")), &stable, &ranked);
+        assert_eq!(out.channel_for(&stable).as_deref(), Some("beta"));
+        let out = token("alpha").restrict(client(Some("beta")), &stable, &ranked);
+        assert_eq!(out.channel_for(&stable).as_deref(), Some("beta"));
+    }
+
+    #[rocket::get("/metrics")]
+    fn metrics(_api_token: ApiToken) -> &'static str {
+        "metrics"
+    }
+
+    #[rocket::get("/update")]
+    fn update(_update_auth: UpdateAuth) -> &'static str {
+        "update"
+    }
+
+    #[test]
+    fn test_jwt_only_accepted_on_update() {
+        use jsonwebtoken::{encode, EncodingKey, Header as JwtHeader};
+        use rocket::http::Header;
+        use rocket::local::Client as LocalClient;
+
+        let jwt = encode(
+            &JwtHeader::default(),
+            &serde_json::json!({"sub": "customer-1", "exp": clock::now() + 60}),
+            &EncodingKey::from_secret(b"secret"),
+        )
+        .unwrap();
+        let serve = |tokens: Tokens| {
+            let config = Config {
+                tokens,
+                jwt: JwtValidator::new(Some("secret"), None, None, None).unwrap(),
+                ..Config::default()
+            };
+            let rocket = rocket::ignite()
+                .manage(config)
+                .mount("/", rocket::routes![metrics, update]);
+            LocalClient::new(rocket).unwrap()
+        };
+        let get = |server: &LocalClient, path: &str, secret: Option<&str>| {
+            let mut request = server.get(path.to_string());
+            if let Some(secret) = secret {
+                request.add_header(Header::new("Authorization", format!("Bearer {}", secret)));
+            }
+            request.dispatch().status()
+        };
+
+        let mut tokens = Tokens::default();
+        tokens.add_secret("ci", "token-secret");
+        let server = serve(tokens);
+        assert_eq!(get(&server, "/update", Some(&jwt)), Status::Ok);
+        assert_eq!(get(&server, "/metrics", Some(&jwt)), Status::Unauthorized);
+        assert_eq!(get(&server, "/metrics", Some("token-secret")), Status::Ok);
+
+        // Without tokens the other endpoints stay open when JWTs are enabled.
+        let server = serve(Tokens::default());
+        assert_eq!(get(&server, "/metrics", None), Status::Ok);
+        assert_eq!(get(&server, "/update", None), Status::BadRequest);
+    }
+
     #[test]
     fn test_base_url() {
         let bu = BaseUrl("https://release.something.inc/product/".to_string());
@@ -3,123 +3,1206 @@
 #[macro_use]
 extern crate rocket;
 
+#[macro_use]
+extern crate log;
+
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::{env, fs, io, time};
 
 use failure::Error;
 
-use rocket::response::content::Json;
-use rocket::response::NamedFile;
-use rocket::State;
+use rocket::http::uri::Uri;
+use rocket::http::{ContentType, RawStr, Status};
+use rocket::request::{Form, FromParam, LenientForm};
+use rocket::response::content::{Content, Html, Json};
+use rocket::response::{status, NamedFile, Redirect};
+use rocket::{Data, State};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use tempfile::NamedTempFile;
 
+use log::LevelFilter;
+use nuts::analytics::{Analytics, Event, EventKind, Interval};
+use nuts::audit::{self, AuditLog};
+use nuts::auth::{self, Tokens};
 use nuts::backend::github::{self, Github};
-use nuts::backend::{Backend, Release};
-use nuts::{ApiToken, BaseUrl, Config, Platform, Signature, Version};
-use rocket::config::Environment;
+use nuts::backend::{self, Backend, Release};
+use nuts::cache::{self, Warmer};
+use nuts::catalogue::{self, Entry, Page};
+use nuts::clock;
+use nuts::cors::{self, Cors};
+use nuts::dashboard::{self, BasicAuthChallenge, Dashboard, SameOrigin};
+use nuts::entitlement::{self, Entitlements};
+use nuts::events::ReleaseEvent;
+use nuts::health::{self, Readiness};
+use nuts::jwt::JwtValidator;
+use nuts::landing::{self, LandingPage, Visitor};
+use nuts::logging::{self, RequestLogger};
+use nuts::metrics::{self, Outcome};
+use nuts::notes::{self, Format};
+use nuts::overrides::{Overrides, PolicyStore};
+use nuts::policy::{self, Policy};
+use nuts::proxy::TrustedProxies;
+use nuts::rate_limit::{Limit, RateLimiter, RetryAfterHeader};
+use nuts::webhook::{self, Kind, Notification, Webhook, Webhooks};
+use nuts::{
+    AdminToken, ApiToken, Arch, Audit, BaseUrl, Client, Config, DashboardToken, LicenceKey,
+    PackageType, Platform, RateLimit, Signature, UpdateAuth, Version,
+};
+use rocket::config::{Environment, LoggingLevel};
 use signed_urls::sign_url;
 
+/// Analytics reports cover this period when no start is given.
+const DEFAULT_REPORT_PERIOD: i64 = 30 * 24 * 60 * 60;
+
 /// Returned by a request to /update
 #[derive(Debug, Serialize, Deserialize)]
 pub struct UpdateResponse {
     url: String,
+    mandatory: bool,
+    notes: String,
 }
 
 fn main() {
+    if env::args().nth(1).as_deref() == Some("hash-token") {
+        return hash_token();
+    }
+    if env::args().nth(1).as_deref() == Some("warm-cache") {
+        return warm_cache();
+    }
+
+    logging::init(
+        env::var("NUTS_LOG_LEVEL").map_or(LevelFilter::Info, |x| {
+            x.parse().expect("invalid NUTS_LOG_LEVEL")
+        }),
+        env::var("NUTS_LOG_FORMAT").map_or(logging::Format::Logfmt, |x| {
+            x.parse().expect("invalid NUTS_LOG_FORMAT")
+        }),
+    )
+    .expect("could not initialize logger");
+
+    let mut tokens = match env::var("NUTS_API_TOKENS_FILE") {
+        Ok(path) => Tokens::load(&path).expect("invalid NUTS_API_TOKENS_FILE"),
+        Err(_) => Tokens::default(),
+    };
+    if let Ok(secret) = env::var("NUTS_SECRET_TOKEN") {
+        tokens.add_secret(auth::DEFAULT_TOKEN_NAME, &secret);
+    }
+
+    let jwt = JwtValidator::load(
+        env::var("NUTS_JWT_SECRET").ok().as_deref(),
+        env::var("NUTS_JWT_JWKS_FILE").ok().as_deref(),
+        env::var("NUTS_JWT_AUDIENCE").ok(),
+        env::var("NUTS_JWT_ISSUER").ok(),
+    )
+    .expect("invalid NUTS_JWT_JWKS_FILE");
+
+    let entitlements = Entitlements::new(
+        env::var("NUTS_ENTITLEMENT_HOOK")
+            .ok()
+            .map(|x| x.parse().expect("invalid NUTS_ENTITLEMENT_HOOK")),
+        env::var("NUTS_ENTITLEMENT_CACHE_TTL").map_or(entitlement::DEFAULT_CACHE_TTL, |x| {
+            time::Duration::from_secs(x.parse().expect("invalid NUTS_ENTITLEMENT_CACHE_TTL"))
+        }),
+    );
+
+    let cache_dir = cache_dir();
+    let audit = AuditLog::open(
+        env::var("NUTS_AUDIT_LOG").map_or_else(|_| cache_dir.join("audit.jsonl"), PathBuf::from),
+        env::var("NUTS_AUDIT_LOG_MAX_SIZE").map_or(audit::DEFAULT_MAX_SIZE, |x| {
+            x.parse().expect("invalid NUTS_AUDIT_LOG_MAX_SIZE")
+        }),
+    )
+    .expect("could not open NUTS_AUDIT_LOG");
+
     let cfg = Config {
-        secret_token: env::var("NUTS_SECRET_TOKEN").ok(),
+        tokens,
+        jwt,
+        entitlements,
         url_signature_secret: env::var("NUTS_URL_SIGNATURE_SECRET").ok(),
         github_repository: env::var("NUTS_GITHUB_REPOSITORY").unwrap_or_default(),
         github_access_token: env::var("NUTS_GITHUB_TOKEN").unwrap_or_default(),
         base_url: env::var("NUTS_BASE_URL").ok(),
+        cache_dir,
+        max_sync_age: env::var("NUTS_READY_MAX_SYNC_AGE")
+            .map_or(health::DEFAULT_MAX_SYNC_AGE, |x| {
+                time::Duration::from_secs(x.parse().expect("invalid NUTS_READY_MAX_SYNC_AGE"))
+            }),
+        trusted_proxies: TrustedProxies::parse(
+            &env::var("NUTS_TRUSTED_PROXIES").unwrap_or_default(),
+        )
+        .expect("invalid NUTS_TRUSTED_PROXIES"),
+        rate_limiter: RateLimiter::new(rate_limits()),
+        audit,
     };
 
-    let backend = Github::new(github::Config {
-        repo: cfg.github_repository.clone(),
-        token: Some(cfg.github_access_token.clone()),
-    });
+    let backend = github_backend(&cfg.github_repository, &cfg.github_access_token);
+
+    let warmer = Warmer::start(
+        backend.clone(),
+        cfg.cache_dir.clone(),
+        env::var("NUTS_CACHE_WARM_CONCURRENCY").map_or(cache::DEFAULT_WARM_CONCURRENCY, |x| {
+            x.parse().expect("invalid NUTS_CACHE_WARM_CONCURRENCY")
+        }),
+    );
+    if env::var("NUTS_CACHE_WARMING").map_or(true, |x| x != "false") {
+        let warmer = warmer.clone();
+        backend.events().subscribe(move |events| {
+            for event in events {
+                if let ReleaseEvent::Added { asset } = event {
+                    warmer.warm(&asset.filename);
+                }
+            }
+        });
+    }
+
+    let webhooks = Webhooks::start(
+        &cfg.github_repository,
+        match env::var("NUTS_WEBHOOKS_FILE") {
+            Ok(path) => Webhook::load(&path).expect("invalid NUTS_WEBHOOKS_FILE"),
+            Err(_) => vec![],
+        },
+        env::var("NUTS_WEBHOOK_RETRIES").map_or(webhook::DEFAULT_RETRIES, |x| {
+            x.parse().expect("invalid NUTS_WEBHOOK_RETRIES")
+        }),
+        webhook::DEFAULT_BACKOFF,
+        cfg.audit.clone(),
+    );
+    {
+        let webhooks = webhooks.clone();
+        backend.events().subscribe(move |events| {
+            for notification in Notification::from_events(events) {
+                webhooks.notify(notification);
+            }
+        });
+    }
+
+    if let Ok(x) = env::var("NUTS_POLL_INTERVAL") {
+        let interval = time::Duration::from_secs(x.parse().expect("invalid NUTS_POLL_INTERVAL"));
+        if interval < github::MIN_POLL_INTERVAL {
+            panic!(
+                "NUTS_POLL_INTERVAL must be at least {} seconds",
+                github::MIN_POLL_INTERVAL.as_secs()
+            );
+        }
+        backend.spawn_poller(interval);
+    }
+
+    let policy = Policy {
+        rollouts: policy::parse_rollouts(&env::var("NUTS_ROLLOUTS").unwrap_or_default())
+            .expect("invalid NUTS_ROLLOUTS"),
+        yanked: policy::parse_versions(&env::var("NUTS_YANKED").unwrap_or_default())
+            .expect("invalid NUTS_YANKED"),
+        critical: policy::parse_versions(&env::var("NUTS_CRITICAL").unwrap_or_default())
+            .expect("invalid NUTS_CRITICAL"),
+        min_version: env::var("NUTS_MIN_VERSION")
+            .ok()
+            .map(|x| Version::from(&x).expect("invalid NUTS_MIN_VERSION")),
+        channels: policy::parse_channels(&env::var("NUTS_CHANNELS").unwrap_or_default()),
+        promotions: Default::default(),
+    };
+    // A channel without a rank is only accepted by clients on that exact channel.
+    if let Some(channel) = prerelease_channel() {
+        if !policy.channels.is_empty() && !policy.channels.contains(&channel) {
+            panic!(
+                "NUTS_GITHUB_PRERELEASE_CHANNEL {} is not one of NUTS_CHANNELS",
+                channel
+            );
+        }
+    }
+    let policy = PolicyStore::open(
+        policy,
+        Some(
+            env::var("NUTS_POLICY_FILE")
+                .map_or_else(|_| cfg.cache_dir.join("policy.json"), PathBuf::from),
+        ),
+    )
+    .expect("invalid NUTS_POLICY_FILE");
 
-    println!("config: {:?}", cfg);
+    let landing_page = LandingPage::new(env::var("NUTS_LANDING_TEMPLATE").ok().as_deref())
+        .expect("invalid NUTS_LANDING_TEMPLATE");
+
+    let analytics = match env::var("NUTS_ANALYTICS_DATABASE") {
+        Ok(path) => Analytics::open(
+            &path,
+            env::var("NUTS_ANALYTICS_RETENTION_DAYS").ok().map(|x| {
+                let days: u64 = x.parse().expect("invalid NUTS_ANALYTICS_RETENTION_DAYS");
+                time::Duration::from_secs(days * 24 * 60 * 60)
+            }),
+            &env::var("NUTS_ANALYTICS_SALT")
+                .expect("NUTS_ANALYTICS_SALT is required with NUTS_ANALYTICS_DATABASE"),
+        )
+        .expect("could not open NUTS_ANALYTICS_DATABASE"),
+        Err(_) => Analytics::disabled(),
+    };
+
+    let cors = Cors::new(
+        &env::var("NUTS_CORS_ORIGINS").unwrap_or_default(),
+        &env::var("NUTS_CORS_METHODS").unwrap_or_else(|_| cors::DEFAULT_METHODS.to_string()),
+        &env::var("NUTS_CORS_HEADERS").unwrap_or_else(|_| cors::DEFAULT_HEADERS.to_string()),
+    );
 
     // TODO: make configurable
     let rocket_config = rocket::Config::build(Environment::Staging)
         .address("0.0.0.0")
         .port(8000)
+        .log_level(LoggingLevel::Off)
         .finalize()
         .unwrap();
 
+    info!(
+        repository = cfg.github_repository,
+        base_url = cfg.base_url,
+        cache_dir:% = cfg.cache_dir.display(),
+        analytics = analytics.is_enabled(),
+        address = rocket_config.address,
+        port = rocket_config.port;
+        "starting nuts"
+    );
+    info!(policy:? = policy.policy(); "release policy");
+
     rocket::custom(rocket_config)
+        // Runs before the request logger, so it logs the status of answered preflight requests.
+        .attach(cors)
+        .attach(RetryAfterHeader)
+        .attach(BasicAuthChallenge)
+        .attach(RequestLogger)
         .manage(backend)
         .manage(cfg)
-        .mount("/", routes![update, download])
+        .manage(policy)
+        .manage(landing_page)
+        .manage(Dashboard::new().expect("invalid dashboard template"))
+        .manage(analytics)
+        .manage(warmer)
+        .manage(webhooks)
+        .mount(
+            "/",
+            routes![
+                index,
+                healthz,
+                readyz,
+                update,
+                download,
+                download_latest,
+                download_latest_target,
+                release_notes,
+                prometheus_metrics,
+                version_report,
+                download_report,
+                catalogue_releases,
+                catalogue_release,
+                catalogue_versions,
+                admin_policy,
+                admin_promote,
+                admin_rollout,
+                admin_yank,
+                admin_unyank,
+                admin_min_version,
+                admin_warm_cache,
+                audit_log,
+                dashboard_index,
+                dashboard_promote,
+                dashboard_yank,
+                dashboard_unyank,
+                dashboard_purge_cache,
+                preflight
+            ],
+        )
         .launch();
 }
 
+/// Reads the rate limits of the update and download routes, e.g. NUTS_RATE_LIMIT_UPDATE=60/m.
+fn rate_limits() -> HashMap<String, Limit> {
+    let mut limits = HashMap::new();
+    for route in &["update", "download"] {
+        let name = format!("NUTS_RATE_LIMIT_{}", route.to_uppercase());
+        if let Ok(x) = env::var(&name) {
+            let limit = x.parse().unwrap_or_else(|_| panic!("invalid {}", name));
+            limits.insert(route.to_string(), limit);
+        }
+    }
+
+    limits
+}
+
+/// Returns the directory release assets are cached in, it is created when it does not exist.
+fn cache_dir() -> PathBuf {
+    let cache_dir = env::var("NUTS_CACHE_DIR").map_or_else(|_| env::temp_dir(), PathBuf::from);
+    fs::create_dir_all(&cache_dir).expect("could not create NUTS_CACHE_DIR");
+    cache_dir
+}
+
+/// Reads the channel of releases marked as pre-release on Github, 'beta' by default. `None` when
+/// they are stable releases.
+fn prerelease_channel() -> Option<String> {
+    let channel = env::var("NUTS_GITHUB_PRERELEASE_CHANNEL")
+        .ok()
+        .map(|x| x.trim().to_lowercase())
+        .filter(|x| !x.is_empty())
+        .unwrap_or_else(|| "beta".to_string());
+
+    Some(channel).filter(|x| x != nuts::STABLE_CHANNEL)
+}
+
+fn github_backend(repository: &str, access_token: &str) -> Github {
+    Github::new(github::Config {
+        repo: repository.to_string(),
+        token: Some(access_token.to_string()),
+        include_drafts: env::var("NUTS_GITHUB_INCLUDE_DRAFTS").map_or(false, |x| x == "true"),
+        prerelease_channel: prerelease_channel(),
+        index_ttl: env::var("NUTS_INDEX_TTL").map_or(github::DEFAULT_INDEX_TTL, |x| {
+            time::Duration::from_secs(x.parse().expect("invalid NUTS_INDEX_TTL"))
+        }),
+    })
+}
+
+/// Downloads the assets of a version into the cache directory, e.g. `nuts warm-cache 1.2.0`.
+fn warm_cache() {
+    let version = env::args()
+        .nth(2)
+        .expect("usage: nuts warm-cache <version>");
+    let version = Version::from(&version).expect("invalid version");
+    let backend = github_backend(
+        &env::var("NUTS_GITHUB_REPOSITORY").unwrap_or_default(),
+        &env::var("NUTS_GITHUB_TOKEN").unwrap_or_default(),
+    );
+    let cache_dir = cache_dir();
+
+    let releases = backend.list_releases().expect("could not list releases");
+    let filenames = cache::version_filenames(&releases, &version);
+    if filenames.is_empty() {
+        eprintln!("no assets found for version {}", version.to_string());
+        std::process::exit(1);
+    }
+
+    for filename in filenames {
+        match cache::fetch(&backend, &cache_dir, &filename) {
+            Ok(true) => println!("downloaded {}", filename),
+            Ok(false) => println!("cached {}", filename),
+            Err(e) => {
+                eprintln!("could not download {}: {}", filename, e);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+/// Reads a token from stdin and prints the hash to configure it with in the tokens file.
+fn hash_token() {
+    let mut token = String::new();
+    io::stdin()
+        .read_line(&mut token)
+        .expect("could not read token from stdin");
+
+    println!("sha256:{}", auth::hash(token.trim()));
+}
+
+/// Renders the landing page, which recommends a download for the platform of the visitor.
+#[get("/")]
+fn index(
+    visitor: Visitor,
+    client: Client,
+    base_url: BaseUrl,
+    backend: State<Github>,
+    policy: State<PolicyStore>,
+    landing_page: State<LandingPage>,
+) -> Result<Html<String>, Status> {
+    let policy = policy.policy();
+    let releases = backend.list_releases().unwrap_or_default();
+    let context =
+        landing::Context::build(&releases, &visitor, &client, &policy, &base_url.to_string());
+
+    landing_page
+        .render(&context)
+        .map(Html)
+        .map_err(|_| Status::InternalServerError)
+}
+
+/// Reports that the process is alive.
+#[get("/healthz")]
+fn healthz() -> Json<&'static str> {
+    Json(r#"{"status":"ok"}"#)
+}
+
+/// Reports whether a release index is loaded, the backend was reachable recently and the cache
+/// directory is writable. Responds with 503 when one of these checks fails.
+#[get("/readyz")]
+fn readyz(config: State<Config>, backend: State<Github>) -> status::Custom<Json<String>> {
+    // Syncs the release index when it has expired.
+    let _ = backend.list_releases();
+
+    let readiness = Readiness::check(
+        backend.sync_status(),
+        &config.cache_dir,
+        config.max_sync_age,
+        clock::now(),
+    );
+    let status = if readiness.ready {
+        Status::Ok
+    } else {
+        Status::ServiceUnavailable
+    };
+
+    status::Custom(status, Json(serde_json::to_string(&readiness).unwrap()))
+}
+
+/// Preflight requests are answered by the CORS fairing, this route keeps them from being
+/// reported as unmatched.
+#[options("/<_path..>")]
+fn preflight(_path: PathBuf) -> Status {
+    Status::NotFound
+}
+
 /// TODO: backend: State<Box<dyn Backend + Sync + Send>>,
-#[get("/update/<platform>/<version>")]
+#[get("/update/<platform>/<version>?<notes>")]
+#[allow(clippy::too_many_arguments)]
 fn update(
     platform: Platform,
     version: Version,
+    notes: Option<Format>,
+    _rate_limit: RateLimit,
     base_url: BaseUrl,
     config: State<Config>,
     backend: State<Github>,
-    _api_token: ApiToken,
-) -> Json<String> {
-    let release = backend.resolve_release(platform, version).unwrap();
+    policy: State<PolicyStore>,
+    analytics: State<Analytics>,
+    client: Client,
+    update_auth: UpdateAuth,
+    licence_key: LicenceKey,
+) -> Result<Json<String>, Status> {
+    let policy = policy.policy();
+    let client = update_auth.restrict(client, &version, &policy);
+    let channel = client.channel_for(&version);
+    if !update_auth.allows_channel(channel.as_deref()) {
+        return Err(Status::Forbidden);
+    }
+
+    let record = |outcome, releases: &[Box<dyn Release>], to: Option<&dyn Release>| {
+        metrics::update_check(
+            platform,
+            channel.as_deref(),
+            &version,
+            releases,
+            &policy,
+            outcome,
+        );
+        record_event(
+            &analytics,
+            Event {
+                kind: EventKind::UpdateCheck,
+                app: config.github_repository.clone(),
+                platform: platform.to_string(),
+                arch: to.and_then(|x| x.get_arch()).map(|x| x.to_string()),
+                from_version: Some(version.to_string()),
+                to_version: to.map(|x| x.get_version().to_string()),
+                channel: channel.clone(),
+                client_id: client.id.clone(),
+            },
+        );
+    };
+
+    // The update and its notes are resolved from one listing, so they are consistent when the
+    // release index is refreshed in the meantime.
+    let releases = backend.list_releases().map_err(|_| {
+        record(Outcome::Error, &[], None);
+        Status::InternalServerError
+    })?;
+    // Updates the licence key does not cover are skipped, the client is told it is not entitled
+    // when there are only such updates.
+    let mut release = None;
+    let mut rejected = false;
+    for x in releases
+        .iter()
+        .filter(|x| backend::is_update(x.as_ref(), platform, &version, &client, &policy))
+    {
+        let covered = licence_key
+            .covers(&config.entitlements, x.get_version())
+            .map_err(|status| {
+                record(Outcome::Error, &releases, None);
+                status
+            })?;
+        if covered {
+            release = Some(x);
+            break;
+        }
+        rejected = true;
+    }
+    let release = release.ok_or_else(|| {
+        if rejected {
+            return Status::Forbidden;
+        }
+        record(Outcome::NoUpdate, &releases, None);
+        Status::NoContent
+    })?;
+    record(Outcome::Update, &releases, Some(release.as_ref()));
+    info!(
+        platform = platform.to_string(),
+        version = version.to_string(),
+        channel = channel,
+        resolved = release.get_version().to_string();
+        "resolved update"
+    );
 
-    Json(
+    Ok(Json(
         serde_json::to_string(&UpdateResponse {
-            url: generate_download_url(&config, &base_url, release).unwrap(),
+            mandatory: policy.is_mandatory(
+                &version,
+                channel.as_deref(),
+                release.as_ref(),
+                &releases,
+            ),
+            notes: notes::render(
+                &notes::aggregate(&releases, &version, release.get_version(), &policy),
+                notes.unwrap_or_default(),
+            ),
+            url: generate_download_url(&config, &base_url, release.as_ref(), licence_key.key())
+                .unwrap(),
         })
         .unwrap(),
+    ))
+}
+
+/// Records an analytics event, a failure is logged and does not fail the request.
+fn record_event(analytics: &Analytics, event: Event) {
+    if let Err(e) = analytics.record(&event) {
+        warn!(kind = event.kind.to_string(), error:% = e; "could not record analytics event");
+    }
+}
+
+/// Exposes metrics in the Prometheus text format.
+#[get("/metrics")]
+fn prometheus_metrics(backend: State<Github>, _api_token: ApiToken) -> Content<String> {
+    if let Ok(remaining) = backend.rate_limit_remaining() {
+        metrics::github_rate_limit_remaining(remaining);
+    }
+
+    Content(
+        ContentType::with_params("text", "plain", ("version", "0.0.4")),
+        metrics::render(),
     )
 }
 
+/// Returns the number of clients per version that checked for updates, grouped by period.
+#[get("/api/analytics/versions?<since>&<until>&<interval>")]
+fn version_report(
+    since: Option<i64>,
+    until: Option<i64>,
+    interval: Option<Interval>,
+    analytics: State<Analytics>,
+    _api_token: ApiToken,
+) -> Result<Json<String>, Status> {
+    let until = until.unwrap_or_else(clock::now);
+    let since = since.unwrap_or(until - DEFAULT_REPORT_PERIOD);
+    let report = analytics
+        .version_distribution(since, until, interval.unwrap_or(Interval::Day))
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(serde_json::to_string(&report).unwrap()))
+}
+
+/// Returns the number of downloads per version and platform, grouped by period.
+#[get("/api/analytics/downloads?<since>&<until>&<interval>")]
+fn download_report(
+    since: Option<i64>,
+    until: Option<i64>,
+    interval: Option<Interval>,
+    analytics: State<Analytics>,
+    _api_token: ApiToken,
+) -> Result<Json<String>, Status> {
+    let until = until.unwrap_or_else(clock::now);
+    let since = since.unwrap_or(until - DEFAULT_REPORT_PERIOD);
+    let report = analytics
+        .downloads(since, until, interval.unwrap_or(Interval::Day))
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(serde_json::to_string(&report).unwrap()))
+}
+
+/// Lists the release assets in the index, filtered on platform, arch, channel and yank state.
+#[get("/api/releases?<query..>")]
+fn catalogue_releases(
+    query: LenientForm<catalogue::Query>,
+    backend: State<Github>,
+    policy: State<PolicyStore>,
+    _api_token: ApiToken,
+) -> Result<Json<String>, Status> {
+    let policy = policy.policy();
+    let releases = backend
+        .list_releases()
+        .map_err(|_| Status::InternalServerError)?;
+    let entries = catalogue::entries(&releases, &query.filter(), &policy);
+
+    Ok(Json(
+        serde_json::to_string(&Page::new(entries, query.offset, query.limit)).unwrap(),
+    ))
+}
+
+/// Returns all release assets of a version.
+#[get("/api/releases/<version>")]
+fn catalogue_release(
+    version: Version,
+    backend: State<Github>,
+    policy: State<PolicyStore>,
+    _api_token: ApiToken,
+) -> Result<Json<String>, Status> {
+    let policy = policy.policy();
+    let releases = backend
+        .list_releases()
+        .map_err(|_| Status::InternalServerError)?;
+    let filter = catalogue::Filter {
+        version: Some(version),
+        ..catalogue::Filter::default()
+    };
+    let entries: Vec<Entry> = catalogue::entries(&releases, &filter, &policy);
+    if entries.is_empty() {
+        return Err(Status::NotFound);
+    }
+
+    Ok(Json(serde_json::to_string(&entries).unwrap()))
+}
+
+/// Lists the versions available for a platform, from new to old.
+#[get("/api/versions/<platform>?<query..>")]
+fn catalogue_versions(
+    platform: Platform,
+    query: LenientForm<catalogue::Query>,
+    backend: State<Github>,
+    policy: State<PolicyStore>,
+    _api_token: ApiToken,
+) -> Result<Json<String>, Status> {
+    let policy = policy.policy();
+    let releases = backend
+        .list_releases()
+        .map_err(|_| Status::InternalServerError)?;
+    let filter = catalogue::Filter {
+        platform: Some(platform),
+        ..query.filter()
+    };
+    let versions = catalogue::versions(&releases, &filter, &policy);
+
+    Ok(Json(
+        serde_json::to_string(&Page::new(versions, query.offset, query.limit)).unwrap(),
+    ))
+}
+
+/// The largest request body accepted by the admin API.
+const MAX_BODY_SIZE: u64 = 64 * 1024;
+
+/// Reads a JSON request body.
+fn read_json<T: DeserializeOwned>(data: Data) -> Result<T, Status> {
+    let mut body = String::new();
+    data.open()
+        .take(MAX_BODY_SIZE)
+        .read_to_string(&mut body)
+        .map_err(|_| Status::BadRequest)?;
+
+    serde_json::from_str(&body).map_err(|_| Status::BadRequest)
+}
+
+/// Changes the policy overrides, records the change in the audit log and responds with all
+/// overrides.
+fn update_policy<F>(
+    policy: &PolicyStore,
+    audit: &Audit,
+    entry: audit::Entry,
+    f: F,
+) -> Result<(Arc<Policy>, Arc<Policy>), Status>
+where
+    F: FnOnce(&mut Overrides) -> Result<(), Error>,
+{
+    let action = entry.action.clone();
+    match policy.update(f) {
+        Ok(x) => {
+            info!(action, version = entry.target; "changed release policy");
+            audit.record(entry);
+            Ok(x)
+        }
+        Err(e) => {
+            warn!(action, error:% = e; "could not change release policy");
+            Err(Status::BadRequest)
+        }
+    }
+}
+
+fn overrides_json(policy: &PolicyStore) -> Json<String> {
+    Json(serde_json::to_string(&policy.overrides()).unwrap())
+}
+
+/// Returns an audit log entry for a change of the policy of a version.
+fn policy_entry(action: &str, version: &Version) -> audit::Entry {
+    audit::Entry::new(&format!("policy.{}", action)).target(&version.to_string())
+}
+
+/// Returns the entries of the audit log, newest first, filtered on time, actor and action.
+#[get("/api/audit?<query..>")]
+fn audit_log(
+    query: LenientForm<audit::Query>,
+    config: State<Config>,
+    _admin_token: AdminToken,
+) -> Result<Json<String>, Status> {
+    let entries = config.audit.query(&query).map_err(|e| {
+        error!(error:% = e; "could not read audit log");
+        Status::InternalServerError
+    })?;
+
+    Ok(Json(
+        serde_json::to_string(&Page::new(entries, query.offset, query.limit)).unwrap(),
+    ))
+}
+
+/// Returns the changes made to the release policy through the admin API.
+#[get("/api/admin/policy")]
+fn admin_policy(policy: State<PolicyStore>, _admin_token: AdminToken) -> Json<String> {
+    Json(serde_json::to_string(&policy.overrides()).unwrap())
+}
+
+#[derive(Debug, Deserialize)]
+struct PromoteRequest {
+    channel: String,
+}
+
+/// Moves a version to another channel, e.g. `{"channel": "stable"}`.
+#[post("/api/admin/releases/<version>/promote", data = "<data>")]
+fn admin_promote(
+    version: Version,
+    data: Data,
+    policy: State<PolicyStore>,
+    _admin_token: AdminToken,
+    audit: Audit,
+) -> Result<Json<String>, Status> {
+    let request: PromoteRequest = read_json(data)?;
+    let entry = policy_entry("promote", &version).detail(&request.channel);
+    update_policy(&policy, &audit, entry, |x| {
+        x.promote(&version, &request.channel)
+    })?;
+    Ok(overrides_json(&policy))
+}
+
+#[derive(Debug, Deserialize)]
+struct RolloutRequest {
+    percentage: u8,
+}
+
+/// Sets the percentage of clients a version is offered to, e.g. `{"percentage": 25}`.
+#[put("/api/admin/releases/<version>/rollout", data = "<data>")]
+fn admin_rollout(
+    version: Version,
+    data: Data,
+    policy: State<PolicyStore>,
+    webhooks: State<Webhooks>,
+    _admin_token: AdminToken,
+    audit: Audit,
+) -> Result<Json<String>, Status> {
+    let request: RolloutRequest = read_json(data)?;
+    let entry = policy_entry("rollout", &version).detail(&request.percentage.to_string());
+    let (before, after) = update_policy(&policy, &audit, entry, |x| {
+        x.set_rollout(&version, request.percentage)
+    })?;
+    let key = version.to_string();
+    if before.rollouts.get(&key) != after.rollouts.get(&key) {
+        webhooks.notify(Notification::new(Kind::RolloutChanged, &key).rollout(request.percentage));
+    }
+    Ok(overrides_json(&policy))
+}
+
+#[post("/api/admin/releases/<version>/yank")]
+fn admin_yank(
+    version: Version,
+    policy: State<PolicyStore>,
+    webhooks: State<Webhooks>,
+    _admin_token: AdminToken,
+    audit: Audit,
+) -> Result<Json<String>, Status> {
+    let (before, after) = update_policy(&policy, &audit, policy_entry("yank", &version), |x| {
+        x.set_yanked(&version, true);
+        Ok(())
+    })?;
+    notify_yanked(&webhooks, &version, &before, &after);
+    Ok(overrides_json(&policy))
+}
+
+/// Notifies the webhooks of a yank, nothing is sent when the version was already yanked.
+fn notify_yanked(webhooks: &Webhooks, version: &Version, before: &Policy, after: &Policy) {
+    if !before.is_yanked(version) && after.is_yanked(version) {
+        webhooks.notify(Notification::new(Kind::ReleaseYanked, &version.to_string()));
+    }
+}
+
+#[post("/api/admin/releases/<version>/unyank")]
+fn admin_unyank(
+    version: Version,
+    policy: State<PolicyStore>,
+    _admin_token: AdminToken,
+    audit: Audit,
+) -> Result<Json<String>, Status> {
+    update_policy(&policy, &audit, policy_entry("unyank", &version), |x| {
+        x.set_yanked(&version, false);
+        Ok(())
+    })?;
+    Ok(overrides_json(&policy))
+}
+
+#[derive(Debug, Deserialize)]
+struct MinVersionRequest {
+    version: Option<String>,
+}
+
+/// Sets the minimum supported version, e.g. `{"version": "1.2.0"}`. A `null` version removes
+/// the minimum.
+#[put("/api/admin/min-version", data = "<data>")]
+fn admin_min_version(
+    data: Data,
+    policy: State<PolicyStore>,
+    _admin_token: AdminToken,
+    audit: Audit,
+) -> Result<Json<String>, Status> {
+    let request: MinVersionRequest = read_json(data)?;
+    let version = match request.version {
+        Some(x) => Some(Version::from(&x).map_err(|_| Status::BadRequest)?),
+        None => None,
+    };
+
+    let mut entry = audit::Entry::new("policy.min_version");
+    if let Some(version) = &version {
+        entry = entry.target(&version.to_string());
+    }
+    update_policy(&policy, &audit, entry, |x| {
+        x.set_min_version(version.as_ref());
+        Ok(())
+    })?;
+    Ok(overrides_json(&policy))
+}
+
+/// Downloads the assets of a version into the cache in the background, returns the filenames of
+/// the assets.
+#[post("/api/admin/releases/<version>/warm-cache")]
+fn admin_warm_cache(
+    version: Version,
+    backend: State<Github>,
+    warmer: State<Warmer>,
+    _admin_token: AdminToken,
+    audit: Audit,
+) -> Result<status::Accepted<Json<String>>, Status> {
+    let releases = backend
+        .list_releases()
+        .map_err(|_| Status::InternalServerError)?;
+    let filenames = cache::version_filenames(&releases, &version);
+    if filenames.is_empty() {
+        return Err(Status::NotFound);
+    }
+
+    for filename in &filenames {
+        warmer.warm(filename);
+    }
+    audit.record(audit::Entry::new("cache.warm").target(&version.to_string()));
+
+    Ok(status::Accepted(Some(Json(
+        serde_json::to_string(&filenames).unwrap(),
+    ))))
+}
+
+/// Returns the filenames of all release assets in the index.
+fn asset_filenames(releases: &[Box<dyn Release>]) -> Vec<String> {
+    releases
+        .iter()
+        .map(|x| x.get_filename().to_string_lossy().to_string())
+        .collect()
+}
+
+/// Renders the admin dashboard.
+#[get("/admin")]
+fn dashboard_index(
+    config: State<Config>,
+    backend: State<Github>,
+    policy: State<PolicyStore>,
+    analytics: State<Analytics>,
+    dashboard: State<Dashboard>,
+    _token: DashboardToken,
+) -> Result<Html<String>, Status> {
+    let policy = policy.policy();
+    let releases = backend
+        .list_releases()
+        .map_err(|_| Status::InternalServerError)?;
+    let until = clock::now();
+    let downloads = analytics
+        .downloads(until - dashboard::DOWNLOAD_PERIOD, until, Interval::Day)
+        .map_err(|_| Status::InternalServerError)?;
+
+    let context = dashboard::Context::build(
+        &config.github_repository,
+        &releases,
+        &policy,
+        cache::usage(&config.cache_dir, &asset_filenames(&releases)),
+        &downloads,
+    );
+
+    dashboard
+        .render(&context)
+        .map(Html)
+        .map_err(|_| Status::InternalServerError)
+}
+
+#[derive(Debug, FromForm)]
+struct PromoteForm {
+    channel: String,
+}
+
+#[post("/admin/releases/<version>/promote", data = "<form>")]
+fn dashboard_promote(
+    version: Version,
+    form: Form<PromoteForm>,
+    policy: State<PolicyStore>,
+    _token: DashboardToken,
+    audit: Audit,
+    _same_origin: SameOrigin,
+) -> Result<Redirect, Status> {
+    let entry = policy_entry("promote", &version).detail(&form.channel);
+    update_policy(&policy, &audit, entry, |x| {
+        x.promote(&version, &form.channel)
+    })?;
+    Ok(Redirect::to(dashboard::PATH))
+}
+
+#[post("/admin/releases/<version>/yank")]
+fn dashboard_yank(
+    version: Version,
+    policy: State<PolicyStore>,
+    webhooks: State<Webhooks>,
+    _token: DashboardToken,
+    audit: Audit,
+    _same_origin: SameOrigin,
+) -> Result<Redirect, Status> {
+    let (before, after) = update_policy(&policy, &audit, policy_entry("yank", &version), |x| {
+        x.set_yanked(&version, true);
+        Ok(())
+    })?;
+    notify_yanked(&webhooks, &version, &before, &after);
+    Ok(Redirect::to(dashboard::PATH))
+}
+
+#[post("/admin/releases/<version>/unyank")]
+fn dashboard_unyank(
+    version: Version,
+    policy: State<PolicyStore>,
+    _token: DashboardToken,
+    audit: Audit,
+    _same_origin: SameOrigin,
+) -> Result<Redirect, Status> {
+    update_policy(&policy, &audit, policy_entry("unyank", &version), |x| {
+        x.set_yanked(&version, false);
+        Ok(())
+    })?;
+    Ok(Redirect::to(dashboard::PATH))
+}
+
+/// Removes the cached release assets, they are downloaded again on the next request.
+#[post("/admin/cache/purge")]
+fn dashboard_purge_cache(
+    config: State<Config>,
+    backend: State<Github>,
+    _token: DashboardToken,
+    audit: Audit,
+    _same_origin: SameOrigin,
+) -> Result<Redirect, Status> {
+    let releases = backend
+        .list_releases()
+        .map_err(|_| Status::InternalServerError)?;
+    let purged = cache::purge(&config.cache_dir, &asset_filenames(&releases)).map_err(|e| {
+        error!(error:% = e; "could not purge cache");
+        Status::InternalServerError
+    })?;
+    info!(files = purged.files, bytes = purged.bytes; "purged cache");
+    audit.record(audit::Entry::new("cache.purge").detail(&format!(
+        "{} files, {}",
+        purged.files,
+        cache::format_bytes(purged.bytes)
+    )));
+
+    Ok(Redirect::to(dashboard::PATH))
+}
+
+/// Returns the combined release notes of all versions after `from` up to and including `to`.
+#[get("/notes/<from>/<to>?<format>")]
+fn release_notes(
+    from: Version,
+    to: Version,
+    format: Option<Format>,
+    backend: State<Github>,
+    policy: State<PolicyStore>,
+    _api_token: ApiToken,
+) -> Result<Content<String>, Status> {
+    let policy = policy.policy();
+    let releases = backend
+        .list_releases()
+        .map_err(|_| Status::InternalServerError)?;
+    let format = format.unwrap_or_default();
+    let markdown = notes::aggregate(&releases, &from, &to, &policy);
+
+    Ok(Content(
+        format.content_type(),
+        notes::render(&markdown, format),
+    ))
+}
+
 #[get("/download/<filename>")]
+#[allow(clippy::too_many_arguments)]
 fn download(
     filename: String,
+    _rate_limit: RateLimit,
+    config: State<Config>,
     backend: State<Github>,
+    policy: State<PolicyStore>,
+    analytics: State<Analytics>,
+    client: Client,
     _signature: Signature,
-) -> io::Result<NamedFile> {
-    let mut cache_path = std::env::temp_dir();
-    cache_path.push(filename.as_str());
-    if fs::metadata(&cache_path).is_err() {
-        let mut tmp_file = NamedTempFile::new()?;
-        backend
-            .download(filename.as_str())
-            .unwrap()
-            .copy_to(&mut tmp_file)
-            .unwrap();
+    licence_key: LicenceKey,
+) -> Result<NamedFile, Status> {
+    let policy = policy.policy();
+    let release = backend
+        .get_release_by_filename(filename.clone())
+        .map_err(|_| Status::NotFound)?;
+
+    if !licence_key.covers(&config.entitlements, release.get_version())? {
+        return Err(Status::Forbidden);
+    }
 
-        std::fs::rename(tmp_file.path(), &cache_path)?;
+    if policy.is_yanked(release.get_version()) {
+        return Err(Status::Gone);
     }
 
-    NamedFile::open(&cache_path)
+    metrics::download(release.as_ref());
+    record_event(
+        &analytics,
+        Event {
+            kind: EventKind::Download,
+            app: config.github_repository.clone(),
+            platform: release.get_platform().to_string(),
+            arch: release.get_arch().map(|x| x.to_string()),
+            from_version: None,
+            to_version: Some(release.get_version().to_string()),
+            channel: policy.channel(release.as_ref()).map(str::to_string),
+            client_id: client.id,
+        },
+    );
+
+    open_cached(&backend, &config.cache_dir, &filename).map_err(|_| Status::InternalServerError)
+}
+
+/// Redirects to the newest release for a platform, in the preferred package type for downloads
+/// on that platform and the architecture of the visitor.
+#[get("/download/latest/<platform>")]
+#[allow(clippy::too_many_arguments)]
+fn download_latest(
+    platform: Platform,
+    visitor: Visitor,
+    _rate_limit: RateLimit,
+    base_url: BaseUrl,
+    config: State<Config>,
+    backend: State<Github>,
+    policy: State<PolicyStore>,
+    client: Client,
+) -> Result<Redirect, Status> {
+    let policy = policy.policy();
+    let package_types = PackageType::for_download(platform);
+    let releases = backend
+        .list_releases()
+        .map_err(|_| Status::InternalServerError)?;
+    let release = visitor
+        .latest(&releases, platform, package_types, &client, &policy)
+        .ok_or(Status::NotFound)?;
+
+    redirect_to_download(&config, &base_url, release)
+}
+
+/// Redirects to the newest release for a platform and either an architecture or a package type,
+/// e.g. `/download/latest/osx/arm64` or `/download/latest/linux/deb`. A package type is resolved
+/// in the architecture of the visitor.
+#[get("/download/latest/<platform>/<target>")]
+#[allow(clippy::too_many_arguments)]
+fn download_latest_target(
+    platform: Platform,
+    target: &RawStr,
+    visitor: Visitor,
+    _rate_limit: RateLimit,
+    base_url: BaseUrl,
+    config: State<Config>,
+    backend: State<Github>,
+    policy: State<PolicyStore>,
+    client: Client,
+) -> Result<Redirect, Status> {
+    let policy = policy.policy();
+    let releases = backend
+        .list_releases()
+        .map_err(|_| Status::InternalServerError)?;
+    let release = match PackageType::from_param(target) {
+        Ok(package_type) => visitor.latest(&releases, platform, &[package_type], &client, &policy),
+        Err(_) => {
+            let arch = Arch::from_param(target).map_err(|_| Status::NotFound)?;
+            let package_types = PackageType::for_download(platform);
+            backend::latest(
+                &releases,
+                platform,
+                Some(arch),
+                package_types,
+                &client,
+                &policy,
+            )
+        }
+    }
+    .ok_or(Status::NotFound)?;
+
+    redirect_to_download(&config, &base_url, release)
+}
+
+fn redirect_to_download(
+    config: &Config,
+    base_url: &BaseUrl,
+    release: &dyn Release,
+) -> Result<Redirect, Status> {
+    let url = generate_download_url(config, base_url, release, None)
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Redirect::found(url))
+}
+
+/// Opens a release asset from the cache, it is downloaded from the backend on a cache miss.
+fn open_cached(backend: &Github, cache_dir: &Path, filename: &str) -> io::Result<NamedFile> {
+    let downloaded = cache::fetch(backend, cache_dir, filename).map_err(|e| {
+        error!(filename, error:% = e; "could not download asset");
+        io::Error::new(io::ErrorKind::Other, e.to_string())
+    })?;
+    metrics::cache(!downloaded);
+    debug!(filename, hit = !downloaded; "asset cache lookup");
+
+    NamedFile::open(cache_dir.join(filename))
 }
 
 fn generate_download_url(
     config: &Config,
     base_url: &BaseUrl,
-    release: Box<dyn Release>,
+    release: &dyn Release,
+    licence_key: Option<&str>,
 ) -> Result<String, Error> {
-    let url = format!(
+    let mut url = format!(
         "{base_url}/download/{filename}",
         base_url = base_url.to_string(),
         filename = release.get_filename().to_str().unwrap()
     );
-
-    println!(
-        "generate_download_url: {} {} {}",
-        base_url.to_string(),
-        release.get_filename().to_str().unwrap(),
-        url
-    );
+    // The licence key is passed on for clients that cannot send headers with the download, the
+    // signature covers it.
+    if let Some(key) = licence_key {
+        url = format!("{}?license_key={}", url, Uri::percent_encode(key));
+    }
 
     if let Some(secret) = &config.url_signature_secret {
         let exp = time::SystemTime::now() + time::Duration::from_secs(60);
         let url = sign_url(secret, url.as_str(), exp)?;
+        debug!(url = logging::redact_url(&url); "generated signed download url");
         return Ok(url);
     }
 
-    Ok(url.to_string())
+    debug!(url = logging::redact_url(&url); "generated download url");
+    Ok(url)
 }
@@ -0,0 +1,248 @@
+use crate::backend::Release;
+use crate::logging;
+use crate::policy::Policy;
+use crate::{Platform, Version, STABLE_CHANNEL};
+use prometheus::{
+    register_histogram_vec, register_int_counter, register_int_counter_vec, register_int_gauge,
+    Encoder, HistogramVec, IntCounter, IntCounterVec, IntGauge, TextEncoder,
+};
+use std::fmt;
+use std::time::Instant;
+
+lazy_static! {
+    static ref UPDATE_CHECKS: IntCounterVec = register_int_counter_vec!(
+        "nuts_update_checks_total",
+        "Number of update checks.",
+        &["platform", "channel", "client_version", "outcome"]
+    )
+    .unwrap();
+    static ref DOWNLOADS: IntCounterVec = register_int_counter_vec!(
+        "nuts_downloads_total",
+        "Number of served release assets.",
+        &["platform", "arch", "package_type"]
+    )
+    .unwrap();
+    static ref CACHE_HITS: IntCounter = register_int_counter!(
+        "nuts_cache_hits_total",
+        "Number of downloads served from the asset cache."
+    )
+    .unwrap();
+    static ref CACHE_MISSES: IntCounter = register_int_counter!(
+        "nuts_cache_misses_total",
+        "Number of downloads that had to be fetched from the backend."
+    )
+    .unwrap();
+    static ref GITHUB_REQUEST_DURATION: HistogramVec = register_histogram_vec!(
+        "nuts_github_request_duration_seconds",
+        "Latency of requests to Github.",
+        &["endpoint"]
+    )
+    .unwrap();
+    static ref GITHUB_ERRORS: IntCounterVec = register_int_counter_vec!(
+        "nuts_github_errors_total",
+        "Number of failed requests to Github.",
+        &["endpoint"]
+    )
+    .unwrap();
+    static ref GITHUB_RATE_LIMIT_REMAINING: IntGauge = register_int_gauge!(
+        "nuts_github_rate_limit_remaining",
+        "Number of requests left in the current Github rate limit window."
+    )
+    .unwrap();
+}
+
+/// The outcome of an update check.
+#[derive(Debug, Clone, Copy)]
+pub enum Outcome {
+    /// A newer release was offered.
+    Update,
+    /// The client is up to date.
+    NoUpdate,
+    /// The update check failed.
+    Error,
+}
+
+impl ToString for Outcome {
+    fn to_string(&self) -> String {
+        match &self {
+            Outcome::Update => "update".to_string(),
+            Outcome::NoUpdate => "no_update".to_string(),
+            Outcome::Error => "error".to_string(),
+        }
+    }
+}
+
+/// Records an update check. To keep the cardinality bounded the client version is reduced to
+/// its major and minor part, versions without a release of that major and minor in the release
+/// index and channels that are not configured are reported as 'other'.
+pub fn update_check(
+    platform: Platform,
+    channel: Option<&str>,
+    version: &Version,
+    releases: &[Box<dyn Release>],
+    policy: &Policy,
+    outcome: Outcome,
+) {
+    let channel = match channel {
+        None => STABLE_CHANNEL,
+        Some(c) if policy.channels.iter().any(|x| x == c) => c,
+        Some(_) => "other",
+    };
+
+    let version = version.inner_version();
+    let known = releases.iter().any(|x| {
+        let x = x.get_version().inner_version();
+        x.major == version.major && x.minor == version.minor
+    });
+    let client_version = if known {
+        format!("{}.{}", version.major, version.minor)
+    } else {
+        "other".to_string()
+    };
+    UPDATE_CHECKS
+        .with_label_values(&[
+            platform.to_string().as_str(),
+            channel,
+            client_version.as_str(),
+            outcome.to_string().as_str(),
+        ])
+        .inc();
+}
+
+/// Records a download of a release asset. To keep the cardinality bounded it is labelled with
+/// the platform, architecture and package type instead of the filename.
+pub fn download(release: &dyn Release) {
+    let arch = release
+        .get_arch()
+        .map_or("any".to_string(), |x| x.to_string());
+    let package_type = release
+        .get_package_type()
+        .map_or("other".to_string(), |x| x.to_string());
+    DOWNLOADS
+        .with_label_values(&[
+            release.get_platform().to_string().as_str(),
+            arch.as_str(),
+            package_type.as_str(),
+        ])
+        .inc();
+}
+
+/// Records whether a download was served from the asset cache.
+pub fn cache(hit: bool) {
+    if hit {
+        CACHE_HITS.inc();
+    } else {
+        CACHE_MISSES.inc();
+    }
+}
+
+/// Records and logs the latency and failure of a request to Github.
+pub fn github_request<T, E: fmt::Display>(
+    endpoint: &str,
+    f: impl FnOnce() -> Result<T, E>,
+) -> Result<T, E> {
+    let start = Instant::now();
+    let result = f();
+    let duration = start.elapsed();
+
+    GITHUB_REQUEST_DURATION
+        .with_label_values(&[endpoint])
+        .observe(duration.as_secs_f64());
+    match &result {
+        Ok(_) => debug!(endpoint, duration_ms = logging::millis(duration); "github request"),
+        Err(e) => {
+            GITHUB_ERRORS.with_label_values(&[endpoint]).inc();
+            warn!(
+                endpoint,
+                duration_ms = logging::millis(duration),
+                error:% = e;
+                "github request failed"
+            );
+        }
+    }
+
+    result
+}
+
+/// Records the remaining Github rate limit.
+pub fn github_rate_limit_remaining(remaining: u32) {
+    GITHUB_RATE_LIMIT_REMAINING.set(i64::from(remaining));
+}
+
+/// Renders all metrics in the Prometheus text format.
+pub fn render() -> String {
+    let mut buffer = vec![];
+    TextEncoder::new()
+        .encode(&prometheus::gather(), &mut buffer)
+        .unwrap();
+
+    String::from_utf8(buffer).unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backend::test_util::release;
+
+    #[test]
+    fn test_update_check_labels() {
+        let policy = Policy {
+            channels: vec!["beta".to_string()],
+            ..Policy::default()
+        };
+        let version = Version::from("1.2.3-nightly.4").unwrap();
+        let releases = vec![release("app-1.2.0-mac.zip"), release("app-1.3.0-mac.zip")];
+
+        update_check(
+            Platform::MacOS,
+            Some("nightly"),
+            &version,
+            &releases,
+            &policy,
+            Outcome::Update,
+        );
+        update_check(
+            Platform::MacOS,
+            Some("beta"),
+            &version,
+            &releases,
+            &policy,
+            Outcome::NoUpdate,
+        );
+        update_check(
+            Platform::MacOS,
+            None,
+            &Version::from("123456.789.0").unwrap(),
+            &releases,
+            &policy,
+            Outcome::Error,
+        );
+
+        let out = render();
+        assert!(out.contains(
+            "nuts_update_checks_total{channel=\"other\",client_version=\"1.2\",outcome=\"update\",platform=\"osx\"} 1"
+        ));
+        assert!(out.contains(
+            "nuts_update_checks_total{channel=\"beta\",client_version=\"1.2\",outcome=\"no_update\",platform=\"osx\"} 1"
+        ));
+        assert!(out.contains(
+            "nuts_update_checks_total{channel=\"stable\",client_version=\"other\",outcome=\"error\",platform=\"osx\"} 1"
+        ));
+        assert!(!out.contains("123456"));
+    }
+
+    #[test]
+    fn test_download_labels() {
+        download(release("app-1.2.0-mac-arm64.zip").as_ref());
+        download(release("app-1.3.0-mac-arm64.zip").as_ref());
+        download(release("app-1.3.0-win.exe").as_ref());
+
+        let out = render();
+        assert!(out.contains(
+            "nuts_downloads_total{arch=\"arm64\",package_type=\"zip\",platform=\"osx\"} 2"
+        ));
+        assert!(out.contains(
+            "nuts_downloads_total{arch=\"any\",package_type=\"exe\",platform=\"win\"} 1"
+        ));
+    }
+}
@@ -0,0 +1,237 @@
+use crate::backend::Release;
+use crate::policy::Policy;
+use crate::Version;
+use pulldown_cmark::{html, Event, Parser, Tag};
+use rocket::http::{ContentType, RawStr};
+use rocket::request::FromFormValue;
+
+/// The format release notes are rendered in.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Format {
+    #[default]
+    Markdown,
+    Html,
+    Text,
+}
+
+impl Format {
+    /// Returns the content-type of release notes in this format.
+    pub fn content_type(self) -> ContentType {
+        match self {
+            Format::Markdown => ContentType::new("text", "markdown"),
+            Format::Html => ContentType::HTML,
+            Format::Text => ContentType::Plain,
+        }
+    }
+}
+
+impl<'v> FromFormValue<'v> for Format {
+    type Error = &'v RawStr;
+
+    fn from_form_value(value: &'v RawStr) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "md" | "markdown" => Ok(Format::Markdown),
+            "html" => Ok(Format::Html),
+            "text" | "txt" | "plain" => Ok(Format::Text),
+            _ => Err(value),
+        }
+    }
+}
+
+/// Combines the notes of all releases after `from` up to and including `to`, newest first and
+/// with a heading per version. Releases are expected to be sorted from new to old, yanked
+/// releases and releases from less stable channels than `to` are left out.
+pub fn aggregate(
+    releases: &[Box<dyn Release>],
+    from: &Version,
+    to: &Version,
+    policy: &Policy,
+) -> String {
+    let channel = releases
+        .iter()
+        .find(|x| x.get_version().inner_version() == to.inner_version())
+        .map_or_else(
+            || to.channel(),
+            |x| policy.channel(x.as_ref()).map(str::to_string),
+        );
+
+    let mut out: Vec<String> = vec![];
+    let mut last: Option<&Version> = None;
+    for release in releases {
+        let version = release.get_version();
+        if version.inner_version() <= from.inner_version()
+            || version.inner_version() > to.inner_version()
+            || last.map_or(false, |x| x.inner_version() == version.inner_version())
+            || policy.is_yanked(version)
+            || !policy.accepts_channel(channel.as_deref(), policy.channel(release.as_ref()))
+        {
+            continue;
+        }
+
+        last = Some(version);
+        out.push(
+            format!(
+                "## {}\n\n{}",
+                version.to_string(),
+                release.get_notes().unwrap_or_default()
+            )
+            .trim()
+            .to_string(),
+        );
+    }
+
+    out.join("\n\n")
+}
+
+/// Renders markdown release notes in the given format.
+pub fn render(markdown: &str, format: Format) -> String {
+    match format {
+        Format::Markdown => markdown.to_string(),
+        Format::Html => {
+            let mut out = String::new();
+            html::push_html(&mut out, Parser::new(markdown).map(sanitize));
+            out
+        }
+        Format::Text => to_text(markdown),
+    }
+}
+
+/// Escapes raw html and drops links with a scheme other than http, https or mailto, as release
+/// notes are written on Github and are shown on pages served by us.
+fn sanitize(event: Event) -> Event {
+    match event {
+        Event::Html(html) => Event::Text(html),
+        Event::Start(Tag::Link(kind, url, title)) if !is_safe_url(&url) => {
+            Event::Start(Tag::Link(kind, "".into(), title))
+        }
+        Event::End(Tag::Link(kind, url, title)) if !is_safe_url(&url) => {
+            Event::End(Tag::Link(kind, "".into(), title))
+        }
+        Event::Start(Tag::Image(kind, url, title)) if !is_safe_url(&url) => {
+            Event::Start(Tag::Image(kind, "".into(), title))
+        }
+        Event::End(Tag::Image(kind, url, title)) if !is_safe_url(&url) => {
+            Event::End(Tag::Image(kind, "".into(), title))
+        }
+        event => event,
+    }
+}
+
+/// Returns whether a url is relative or uses the http, https or mailto scheme. Browsers ignore
+/// whitespace and control characters in a scheme, so these are ignored here as well.
+fn is_safe_url(url: &str) -> bool {
+    let url: String = url
+        .chars()
+        .filter(|c| !c.is_ascii_whitespace() && !c.is_ascii_control())
+        .collect::<String>()
+        .to_lowercase();
+
+    match url.find(|c| c == ':' || c == '/' || c == '?' || c == '#') {
+        Some(i) if url[i..].starts_with(':') => {
+            matches!(&url[..i], "http" | "https" | "mailto")
+        }
+        _ => true,
+    }
+}
+
+/// Strips all markdown formatting, list items are prefixed with a dash.
+fn to_text(markdown: &str) -> String {
+    let mut out = String::new();
+
+    for event in Parser::new(markdown) {
+        match event {
+            Event::Text(text) | Event::Code(text) => out.push_str(&text),
+            Event::SoftBreak | Event::HardBreak => out.push('\n'),
+            Event::Start(Tag::Item) => out.push_str("- "),
+            Event::End(Tag::Paragraph) | Event::End(Tag::Heading(..)) => out.push_str("\n\n"),
+            Event::End(Tag::Item) => out.push('\n'),
+            Event::End(Tag::List(_)) => out.push('\n'),
+            _ => {}
+        }
+    }
+
+    out.trim().to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backend::test_util::TestRelease;
+
+    fn release(filename: &str) -> Box<dyn Release> {
+        let release = TestRelease::new(filename);
+        TestRelease {
+            notes: Some(format!("- Changes in {}", release.version.to_string())),
+            ..release
+        }
+        .boxed()
+    }
+
+    #[test]
+    fn test_aggregate() {
+        let releases = vec![
+            release("app-1.7.0-mac.zip"),
+            release("app-1.6.0-mac.zip"),
+            release("app-1.6.0-win.exe"),
+            release("app-1.5.0-mac.zip"),
+            release("app-1.4.0-mac.zip"),
+            release("app-1.2.0-mac.zip"),
+        ];
+        let v = |x| Version::from(x).unwrap();
+        let policy = Policy {
+            yanked: vec!["1.5.0".to_string()].into_iter().collect(),
+            ..Policy::default()
+        };
+
+        assert_eq!(
+            aggregate(&releases, &v("1.2.0"), &v("1.6.0"), &policy),
+            "## 1.6.0\n\n- Changes in 1.6.0\n\n## 1.4.0\n\n- Changes in 1.4.0"
+        );
+        assert_eq!(aggregate(&releases, &v("1.6.0"), &v("1.6.0"), &policy), "");
+    }
+
+    #[test]
+    fn test_render() {
+        let markdown = "## 1.6.0\n\nFixed **crashes**:\n\n- on start\n- on exit";
+        assert_eq!(render(markdown, Format::Markdown), markdown);
+        assert_eq!(
+            render(markdown, Format::Html),
+            "<h2>1.6.0</h2>\n<p>Fixed <strong>crashes</strong>:</p>\n<ul>\n<li>on start</li>\n<li>on exit</li>\n</ul>\n"
+        );
+        assert_eq!(
+            render(markdown, Format::Text),
+            "1.6.0\n\nFixed crashes:\n\n- on start\n- on exit"
+        );
+    }
+
+    #[test]
+    fn test_render_html_sanitized() {
+        assert_eq!(
+            render("<script>alert(1)</script>", Format::Html),
+            "&lt;script&gt;alert(1)&lt;/script&gt;"
+        );
+        assert_eq!(
+            render("Fixed <img src=x onerror=alert(1)>", Format::Html),
+            "<p>Fixed &lt;img src=x onerror=alert(1)&gt;</p>\n"
+        );
+        assert_eq!(
+            render("[docs](JavaScript:alert(1))", Format::Html),
+            "<p><a href=\"\">docs</a></p>\n"
+        );
+        assert_eq!(
+            render("[docs](https://example.com/docs)", Format::Html),
+            "<p><a href=\"https://example.com/docs\">docs</a></p>\n"
+        );
+    }
+
+    #[test]
+    fn test_is_safe_url() {
+        assert!(is_safe_url("https://example.com"));
+        assert!(is_safe_url("mailto:support@example.com"));
+        assert!(is_safe_url("/docs/a:b"));
+        assert!(is_safe_url("#changes"));
+        assert!(!is_safe_url("javascript:alert(1)"));
+        assert!(!is_safe_url("java\tscript:alert(1)"));
+        assert!(!is_safe_url("data:text/html,<script>"));
+    }
+}
@@ -0,0 +1,237 @@
+use crate::policy::Policy;
+use crate::Version;
+use failure::Error;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use tempfile::NamedTempFile;
+
+/// Changes to the release policy made through the admin API, layered on top of the policy from
+/// the environment.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Overrides {
+    /// Channel per version, 'stable' for the stable channel.
+    #[serde(default)]
+    pub promotions: HashMap<String, String>,
+
+    /// Rollout percentage per version.
+    #[serde(default)]
+    pub rollouts: HashMap<String, u8>,
+
+    /// Whether a version is yanked, `false` unyanks a version that is yanked in the environment.
+    #[serde(default)]
+    pub yanked: HashMap<String, bool>,
+
+    /// The minimum supported version, `Some(None)` removes the minimum of the environment.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_some",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub min_version: Option<Option<String>>,
+}
+
+impl Overrides {
+    /// Returns the policy with the overrides applied.
+    pub fn apply(&self, base: &Policy) -> Result<Policy, Error> {
+        let mut policy = base.clone();
+        policy.promotions.extend(self.promotions.clone());
+        policy.rollouts.extend(self.rollouts.clone());
+
+        for (version, yanked) in &self.yanked {
+            if *yanked {
+                policy.yanked.insert(version.clone());
+            } else {
+                policy.yanked.remove(version);
+            }
+        }
+
+        if let Some(min_version) = &self.min_version {
+            policy.min_version = match min_version {
+                Some(x) => Some(Version::from(x)?),
+                None => None,
+            };
+        }
+
+        Ok(policy)
+    }
+
+    pub fn promote(&mut self, version: &Version, channel: &str) -> Result<(), Error> {
+        let channel = channel.trim().to_lowercase();
+        if channel.is_empty() {
+            bail!("Missing channel");
+        }
+
+        self.promotions.insert(version.to_string(), channel);
+        Ok(())
+    }
+
+    pub fn set_rollout(&mut self, version: &Version, percentage: u8) -> Result<(), Error> {
+        if percentage > 100 {
+            bail!("Invalid rollout percentage {}", percentage);
+        }
+
+        self.rollouts.insert(version.to_string(), percentage);
+        Ok(())
+    }
+
+    pub fn set_yanked(&mut self, version: &Version, yanked: bool) {
+        self.yanked.insert(version.to_string(), yanked);
+    }
+
+    pub fn set_min_version(&mut self, version: Option<&Version>) {
+        self.min_version = Some(version.map(Version::to_string));
+    }
+}
+
+/// Distinguishes a missing field, which is `None`, from a `null` field, which is `Some(None)`.
+fn deserialize_some<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+/// Keeps the overrides in a JSON file and the policy that results from applying them, changes
+/// take effect for the next request.
+#[derive(Debug)]
+pub struct PolicyStore {
+    base: Policy,
+    path: Option<PathBuf>,
+    state: RwLock<(Overrides, Arc<Policy>)>,
+}
+
+impl PolicyStore {
+    /// Reads the overrides from a file, the file is created on the first change. Without a path
+    /// the overrides are only kept in memory.
+    pub fn open(base: Policy, path: Option<PathBuf>) -> Result<Self, Error> {
+        let overrides = match &path {
+            Some(path) if path.exists() => serde_json::from_str(&fs::read_to_string(path)?)?,
+            _ => Overrides::default(),
+        };
+        let policy = overrides.apply(&base)?;
+
+        Ok(PolicyStore {
+            base,
+            path,
+            state: RwLock::new((overrides, Arc::new(policy))),
+        })
+    }
+
+    /// Returns the current policy.
+    pub fn policy(&self) -> Arc<Policy> {
+        self.state.read().unwrap().1.clone()
+    }
+
+    pub fn overrides(&self) -> Overrides {
+        self.state.read().unwrap().0.clone()
+    }
+
+    /// Changes the overrides and stores them, returns the policy before and after the change.
+    /// Nothing changes when `f` or storing fails.
+    pub fn update<F>(&self, f: F) -> Result<(Arc<Policy>, Arc<Policy>), Error>
+    where
+        F: FnOnce(&mut Overrides) -> Result<(), Error>,
+    {
+        let mut state = self.state.write().unwrap();
+        let mut overrides = state.0.clone();
+        f(&mut overrides)?;
+        let policy = Arc::new(overrides.apply(&self.base)?);
+
+        if let Some(path) = &self.path {
+            let dir = path.parent().filter(|x| !x.as_os_str().is_empty());
+            let mut file = NamedTempFile::new_in(dir.unwrap_or_else(|| ".".as_ref()))?;
+            file.write_all(serde_json::to_string_pretty(&overrides)?.as_bytes())?;
+            file.persist(path)?;
+        }
+
+        let previous = std::mem::replace(&mut *state, (overrides, policy.clone())).1;
+        Ok((previous, policy))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::policy;
+
+    fn v(s: &str) -> Version {
+        Version::from(s).unwrap()
+    }
+
+    #[test]
+    fn test_apply() {
+        let base = Policy {
+            yanked: policy::parse_versions("1.1.0").unwrap(),
+            min_version: Some(v("1.0.0")),
+            ..Policy::default()
+        };
+
+        let mut overrides = Overrides::default();
+        overrides.promote(&v("1.3.0-beta.1"), "Stable").unwrap();
+        overrides.promote(&v("1.2.0"), "beta").unwrap();
+        overrides.set_rollout(&v("1.3.0-beta.1"), 20).unwrap();
+        overrides.set_yanked(&v("1.1.0"), false);
+        overrides.set_yanked(&v("1.2.0"), true);
+        assert!(overrides.set_rollout(&v("1.3.0"), 101).is_err());
+        assert!(overrides.promote(&v("1.3.0"), " ").is_err());
+
+        let policy = overrides.apply(&base).unwrap();
+        assert_eq!(
+            policy.promotions.get("1.3.0-beta.1").map(String::as_str),
+            Some("stable")
+        );
+        assert_eq!(
+            policy.promotions.get("1.2.0").map(String::as_str),
+            Some("beta")
+        );
+        assert_eq!(policy.rollouts.get("1.3.0-beta.1"), Some(&20));
+        assert!(!policy.is_yanked(&v("1.1.0")));
+        assert!(policy.is_yanked(&v("1.2.0")));
+        assert_eq!(policy.min_version, Some(v("1.0.0")));
+
+        overrides.set_min_version(None);
+        assert_eq!(overrides.apply(&base).unwrap().min_version, None);
+    }
+
+    #[test]
+    fn test_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("policy.json");
+
+        let store = PolicyStore::open(Policy::default(), Some(path.clone())).unwrap();
+        assert_eq!(store.policy().min_version, None);
+
+        let (before, after) = store
+            .update(|x| {
+                x.set_min_version(Some(&v("1.2.0")));
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(before.min_version, None);
+        assert_eq!(after.min_version, Some(v("1.2.0")));
+        assert_eq!(store.policy().min_version, Some(v("1.2.0")));
+
+        // A failing change leaves the policy as it was.
+        assert!(store.update(|x| x.set_rollout(&v("1.2.0"), 200)).is_err());
+        assert!(store.overrides().rollouts.is_empty());
+
+        let store = PolicyStore::open(Policy::default(), Some(path)).unwrap();
+        assert_eq!(store.policy().min_version, Some(v("1.2.0")));
+    }
+
+    #[test]
+    fn test_deserialize_min_version() {
+        let x: Overrides = serde_json::from_str("{}").unwrap();
+        assert_eq!(x.min_version, None);
+        let x: Overrides = serde_json::from_str(r#"{"min_version": null}"#).unwrap();
+        assert_eq!(x.min_version, Some(None));
+
+        let json = serde_json::to_string(&x).unwrap();
+        assert_eq!(serde_json::from_str::<Overrides>(&json).unwrap(), x);
+    }
+}
@@ -0,0 +1,314 @@
+use crate::backend::Release;
+use crate::{Client, Version, STABLE_CHANNEL};
+use failure::Error;
+use std::collections::{HashMap, HashSet};
+
+/// Release policy that is applied on top of the releases reported by a backend.
+#[derive(Debug, Clone, Default)]
+pub struct Policy {
+    /// Rollout percentage per version, takes precedence over the release metadata.
+    pub rollouts: HashMap<String, u8>,
+
+    /// Versions that must never be offered or served to clients.
+    pub yanked: HashSet<String>,
+
+    /// Versions that clients must update to, takes precedence over the release metadata.
+    pub critical: HashSet<String>,
+
+    /// Clients below this version must update.
+    pub min_version: Option<Version>,
+
+    /// Pre-release channels ordered from least to most stable, e.g. `["alpha", "beta"]`. Clients
+    /// are offered releases from their own channel and every more stable one. Channels that are
+    /// not listed only receive releases from their own channel.
+    pub channels: Vec<String>,
+
+    /// Channel per version that releases have been promoted to, 'stable' for the stable channel.
+    pub promotions: HashMap<String, String>,
+}
+
+impl Policy {
+    /// Returns the percentage of clients a release is offered to.
+    pub fn rollout(&self, release: &dyn Release) -> u8 {
+        self.rollouts
+            .get(&release.get_version().to_string())
+            .cloned()
+            .or_else(|| release.get_rollout())
+            .unwrap_or(100)
+    }
+
+    /// Returns the channel of a release, a promotion takes precedence over the channel reported
+    /// by the backend. The stable channel is represented by `None`.
+    pub fn channel<'a>(&'a self, release: &'a dyn Release) -> Option<&'a str> {
+        match self.promotions.get(&release.get_version().to_string()) {
+            Some(x) if x == STABLE_CHANNEL => None,
+            Some(x) => Some(x.as_str()),
+            None => release.get_channel(),
+        }
+    }
+
+    /// Returns true when a version has been yanked.
+    pub fn is_yanked(&self, version: &Version) -> bool {
+        self.yanked.contains(&version.to_string())
+    }
+
+    /// Returns true when a client on the given channel may be offered releases from the release
+    /// channel. The stable channel is represented by `None`.
+    pub fn accepts_channel(&self, client: Option<&str>, release: Option<&str>) -> bool {
+        if client == release {
+            return true;
+        }
+
+        match (self.channel_rank(client), self.channel_rank(release)) {
+            (Some(c), Some(r)) => r >= c,
+            _ => false,
+        }
+    }
+
+    /// Returns the stability of a channel, the stable channel ranks above all others.
+    fn channel_rank(&self, channel: Option<&str>) -> Option<usize> {
+        match channel {
+            Some(c) => self.channels.iter().position(|x| x == c),
+            None => Some(self.channels.len()),
+        }
+    }
+
+    /// Returns true when a release is marked as critical.
+    pub fn is_critical(&self, release: &dyn Release) -> bool {
+        self.critical.contains(&release.get_version().to_string()) || release.is_critical()
+    }
+
+    /// Returns true when a client on the current version must update to the target release. This
+    /// is the case when the current version is below the minimum supported version, or when any
+    /// release that is skipped (including the target) is critical. Only releases on channels the
+    /// client accepts are considered.
+    pub fn is_mandatory(
+        &self,
+        current: &Version,
+        channel: Option<&str>,
+        target: &dyn Release,
+        releases: &[Box<dyn Release>],
+    ) -> bool {
+        if let Some(min) = &self.min_version {
+            if current.inner_version() < min.inner_version() {
+                return true;
+            }
+        }
+
+        releases.iter().any(|x| {
+            x.get_platform() == target.get_platform()
+                && x.get_version().inner_version() > current.inner_version()
+                && x.get_version().inner_version() <= target.get_version().inner_version()
+                && self.accepts_channel(channel, self.channel(x.as_ref()))
+                && self.is_critical(x.as_ref())
+        })
+    }
+
+    /// Returns true when a release may be offered to the given client. Clients without an
+    /// identifier are only offered fully rolled out releases.
+    pub fn is_eligible(&self, release: &dyn Release, client: &Client) -> bool {
+        if self.is_yanked(release.get_version()) {
+            return false;
+        }
+
+        if let Some(max) = &client.max_version {
+            if release.get_version().inner_version() > max.inner_version() {
+                return false;
+            }
+        }
+
+        let rollout = self.rollout(release);
+        if rollout >= 100 {
+            return true;
+        }
+
+        match &client.id {
+            Some(id) => bucket(id, release.get_version()) < rollout,
+            None => false,
+        }
+    }
+}
+
+/// Parses rollout percentages in the form of '<version>=<percentage>,...'.
+pub fn parse_rollouts(s: &str) -> Result<HashMap<String, u8>, Error> {
+    let mut out = HashMap::new();
+
+    for pair in s.split(',').map(str::trim).filter(|x| !x.is_empty()) {
+        let mut parts = pair.splitn(2, '=');
+        let version = Version::from(parts.next().unwrap_or_default().trim())?;
+        let percentage: u8 = match parts.next() {
+            Some(p) => p.trim().trim_end_matches('%').parse()?,
+            None => bail!("Missing rollout percentage for {}", pair),
+        };
+
+        if percentage > 100 {
+            bail!("Invalid rollout percentage for {}", pair);
+        }
+
+        out.insert(version.to_string(), percentage);
+    }
+
+    Ok(out)
+}
+
+/// Parses a comma separated list of channels, ordered from least to most stable.
+pub fn parse_channels(s: &str) -> Vec<String> {
+    s.split(',')
+        .map(|x| x.trim().to_lowercase())
+        .filter(|x| !x.is_empty() && x != STABLE_CHANNEL)
+        .collect()
+}
+
+/// Parses a comma separated list of versions.
+pub fn parse_versions(s: &str) -> Result<HashSet<String>, Error> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|x| !x.is_empty())
+        .map(|x| Ok(Version::from(x)?.to_string()))
+        .collect()
+}
+
+/// Returns the bucket (0-99) a client falls into for a version. The bucket is stable, so a
+/// client stays included when the rollout of that version is widened.
+pub fn bucket(client_id: &str, version: &Version) -> u8 {
+    // FNV-1a, unlike the std hashers its output is guaranteed to be stable between releases.
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    let input = format!("{}:{}", client_id, version.to_string());
+    for byte in input.bytes() {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+
+    (hash % 100) as u8
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backend::test_util::TestRelease;
+
+    fn release(version: &str, critical: bool) -> Box<dyn Release> {
+        TestRelease {
+            critical,
+            ..TestRelease::new(&format!("app-{}-mac.zip", version))
+        }
+        .boxed()
+    }
+
+    #[test]
+    fn test_parse_rollouts() {
+        let rollouts = parse_rollouts("v1.2.0=5, 2.0.0-beta.1=50%,").unwrap();
+        assert_eq!(rollouts.get("1.2.0"), Some(&5));
+        assert_eq!(rollouts.get("2.0.0-beta.1"), Some(&50));
+
+        assert!(parse_rollouts("").unwrap().is_empty());
+        assert!(parse_rollouts("1.2.0").is_err());
+        assert!(parse_rollouts("1.2.0=101").is_err());
+        assert!(parse_rollouts("latest=5").is_err());
+    }
+
+    #[test]
+    fn test_parse_versions() {
+        let versions = parse_versions("v1.2.0, 1.3.0-beta.2").unwrap();
+        assert!(versions.contains("1.2.0"));
+        assert!(versions.contains("1.3.0-beta.2"));
+        assert!(parse_versions("1.2").is_err());
+    }
+
+    #[test]
+    fn test_is_mandatory() {
+        let releases = vec![
+            release("1.4.0", false),
+            release("1.3.0", true),
+            release("1.2.0", false),
+        ];
+        let target = &releases[0];
+        let v = |x| Version::from(x).unwrap();
+
+        let policy = Policy::default();
+        assert!(policy.is_mandatory(&v("1.2.0"), None, target.as_ref(), &releases));
+        assert!(!policy.is_mandatory(&v("1.3.0"), None, target.as_ref(), &releases));
+
+        let policy = Policy {
+            min_version: Some(v("1.3.5")),
+            ..Policy::default()
+        };
+        assert!(policy.is_mandatory(&v("1.3.0"), None, target.as_ref(), &releases));
+
+        let policy = Policy {
+            critical: parse_versions("1.4.0").unwrap(),
+            ..Policy::default()
+        };
+        assert!(policy.is_mandatory(&v("1.3.0"), None, target.as_ref(), &releases));
+        // A critical release on a channel the client does not accept is not skipped by it.
+        let releases = vec![
+            release("1.4.0", false),
+            TestRelease {
+                channel: Some("beta".to_string()),
+                critical: true,
+                ..TestRelease::new("app-1.3.0-mac.zip")
+            }
+            .boxed(),
+        ];
+        let target = &releases[0];
+        let policy = Policy::default();
+        assert!(!policy.is_mandatory(&v("1.2.0"), None, target.as_ref(), &releases));
+        assert!(policy.is_mandatory(&v("1.2.0"), Some("beta"), target.as_ref(), &releases));
+    }
+
+    #[test]
+    fn test_accepts_channel() {
+        let policy = Policy::default();
+        assert!(policy.accepts_channel(None, None));
+        assert!(policy.accepts_channel(Some("beta"), Some("beta")));
+        assert!(!policy.accepts_channel(Some("beta"), None));
+        assert!(!policy.accepts_channel(None, Some("beta")));
+
+        let policy = Policy {
+            channels: parse_channels("alpha, beta, stable"),
+            ..Policy::default()
+        };
+        assert!(policy.accepts_channel(Some("alpha"), Some("beta")));
+        assert!(policy.accepts_channel(Some("alpha"), None));
+        assert!(policy.accepts_channel(Some("beta"), None));
+        assert!(!policy.accepts_channel(Some("beta"), Some("alpha")));
+        assert!(!policy.accepts_channel(None, Some("beta")));
+        assert!(!policy.accepts_channel(Some("nightly"), None));
+    }
+
+    #[test]
+    fn test_channel() {
+        let beta = TestRelease {
+            version: Version::from("1.3.0-beta.1").unwrap(),
+            channel: Some("beta".to_string()),
+            ..TestRelease::new("app-1.3.0-mac.zip")
+        };
+        let mut policy = Policy::default();
+        assert_eq!(policy.channel(&beta), Some("beta"));
+
+        policy
+            .promotions
+            .insert("1.3.0-beta.1".to_string(), "stable".to_string());
+        assert_eq!(policy.channel(&beta), None);
+
+        policy
+            .promotions
+            .insert("1.2.0".to_string(), "beta".to_string());
+        assert_eq!(
+            policy.channel(release("1.2.0", false).as_ref()),
+            Some("beta")
+        );
+        assert_eq!(policy.channel(release("1.1.0", false).as_ref()), None);
+    }
+
+    #[test]
+    fn test_bucket() {
+        let version = Version::from("1.2.0").unwrap();
+        assert_eq!(bucket("client-a", &version), bucket("client-a", &version));
+
+        let included = (0..1000)
+            .filter(|i| bucket(&format!("client-{}", i), &version) < 10)
+            .count();
+        assert!(included > 50 && included < 150, "{}", included);
+    }
+}
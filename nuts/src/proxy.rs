@@ -0,0 +1,364 @@
+use failure::Error;
+use rocket::http::HeaderMap;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// A range of ip addresses, e.g. '10.0.0.0/8'. A bare address is a range of one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            (IpAddr::V6(_), IpAddr::V4(ip)) => self.contains(IpAddr::V6(ip.to_ipv6_mapped())),
+            (IpAddr::V4(_), IpAddr::V6(ip)) => ip
+                .to_ipv4_mapped()
+                .map_or(false, |x| self.contains(IpAddr::V4(x))),
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match s.find('/') {
+            Some(i) => (&s[..i], Some(&s[i + 1..])),
+            None => (s, None),
+        };
+
+        let addr: IpAddr = addr.parse()?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(x) => x.parse()?,
+            None => max,
+        };
+        if prefix > max {
+            bail!("Invalid prefix length {}", s);
+        }
+
+        Ok(Cidr { addr, prefix })
+    }
+}
+
+/// The proxies whose forwarding headers are honoured.
+#[derive(Debug, Default)]
+pub struct TrustedProxies(Vec<Cidr>);
+
+impl TrustedProxies {
+    /// Parses a comma separated list of addresses and ranges.
+    pub fn parse(s: &str) -> Result<Self, Error> {
+        let mut out = vec![];
+        for x in s.split(',').map(str::trim).filter(|x| !x.is_empty()) {
+            out.push(x.parse()?);
+        }
+
+        Ok(TrustedProxies(out))
+    }
+
+    pub fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.0.iter().any(|x| x.contains(ip))
+    }
+
+    /// Returns the host requested by the client, the forwarding headers are only used when the
+    /// request comes from a trusted proxy. The value added by the nearest trusted proxy is used,
+    /// values further to the left may have been sent by the client.
+    pub fn host(&self, headers: &HeaderMap, peer: Option<IpAddr>) -> Option<String> {
+        if peer.map_or(false, |x| self.is_trusted(x)) {
+            let forwarded = self
+                .trusted_elements(headers)
+                .into_iter()
+                .find_map(|x| x.host)
+                .or_else(|| last_value(headers, "X-Forwarded-Host"));
+            if forwarded.is_some() {
+                return forwarded;
+            }
+        }
+
+        headers.get_one("Host").map(str::to_string)
+    }
+
+    /// Returns the scheme requested by the client, the forwarding headers are only used when the
+    /// request comes from a trusted proxy. See `host`.
+    pub fn proto(&self, headers: &HeaderMap, peer: Option<IpAddr>) -> Option<String> {
+        if !peer.map_or(false, |x| self.is_trusted(x)) {
+            return None;
+        }
+
+        self.trusted_elements(headers)
+            .into_iter()
+            .find_map(|x| x.proto)
+            .or_else(|| last_value(headers, "X-Forwarded-Proto"))
+            .map(|x| x.to_lowercase())
+    }
+
+    /// Returns the elements of the 'Forwarded' header that were added by trusted proxies, nearest
+    /// first. The last element is added by the peer, which is expected to be trusted, and every
+    /// element before it by the address named in the element after it.
+    fn trusted_elements(&self, headers: &HeaderMap) -> Vec<ForwardedElement> {
+        let mut out = vec![];
+        for element in parse_forwarded(headers).into_iter().rev() {
+            let next_trusted = element
+                .for_
+                .as_deref()
+                .and_then(parse_node)
+                .map_or(false, |x| self.is_trusted(x));
+            out.push(element);
+            if !next_trusted {
+                break;
+            }
+        }
+
+        out
+    }
+
+    /// Returns the address of the client. Starting at the peer, the chain of forwarded addresses
+    /// is followed from right to left for as long as the hops are trusted proxies.
+    pub fn client_ip(&self, headers: &HeaderMap, peer: Option<IpAddr>) -> Option<IpAddr> {
+        let mut ip = peer?;
+        if !self.is_trusted(ip) {
+            return Some(ip);
+        }
+
+        let forwarded = parse_forwarded(headers);
+        let chain: Vec<String> = if forwarded.is_empty() {
+            headers
+                .get("X-Forwarded-For")
+                .flat_map(|x| x.split(','))
+                .map(|x| x.trim().to_string())
+                .collect()
+        } else {
+            forwarded.into_iter().filter_map(|x| x.for_).collect()
+        };
+
+        for hop in chain.iter().rev() {
+            ip = match parse_node(hop) {
+                Some(x) => x,
+                None => break,
+            };
+            if !self.is_trusted(ip) {
+                break;
+            }
+        }
+
+        Some(ip)
+    }
+}
+
+/// An element of the RFC 7239 'Forwarded' header, added by a single proxy.
+#[derive(Debug, Default, PartialEq)]
+pub struct ForwardedElement {
+    pub for_: Option<String>,
+    pub host: Option<String>,
+    pub proto: Option<String>,
+}
+
+/// Parses all 'Forwarded' headers, elements are returned in the order they were added.
+pub fn parse_forwarded(headers: &HeaderMap) -> Vec<ForwardedElement> {
+    let mut out = vec![];
+    for header in headers.get("Forwarded") {
+        for element in header.split(',') {
+            let mut x = ForwardedElement::default();
+            for pair in element.split(';') {
+                let mut parts = pair.splitn(2, '=');
+                let key = parts.next().unwrap_or_default().trim().to_lowercase();
+                let value = match parts.next() {
+                    Some(value) => value.trim().trim_matches('"').to_string(),
+                    None => continue,
+                };
+
+                match key.as_str() {
+                    "for" => x.for_ = Some(value),
+                    "host" => x.host = Some(value),
+                    "proto" => x.proto = Some(value),
+                    _ => {}
+                }
+            }
+            out.push(x);
+        }
+    }
+
+    out
+}
+
+/// Returns the last of the comma separated values of a header, which is the value added by the
+/// nearest proxy.
+fn last_value(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get(name)
+        .flat_map(|x| x.split(','))
+        .last()
+        .map(|x| x.trim().to_string())
+        .filter(|x| !x.is_empty())
+}
+
+/// Parses a forwarded node, e.g. '192.0.2.43', '192.0.2.43:47011' or '[2001:db8::17]:4711'.
+/// Obfuscated identifiers and 'unknown' are not addresses.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    if let Ok(ip) = node.parse() {
+        return Some(ip);
+    }
+
+    if let Some(node) = node.strip_prefix('[') {
+        return node.split(']').next().and_then(|x| x.parse().ok());
+    }
+
+    node.rsplit_once(':').and_then(|(ip, _)| ip.parse().ok())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rocket::http::Header;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap<'static> {
+        let mut out = HeaderMap::new();
+        for (name, value) in pairs {
+            out.add(Header::new(*name, *value));
+        }
+        out
+    }
+
+    fn ip(s: &str) -> Option<IpAddr> {
+        Some(s.parse().unwrap())
+    }
+
+    #[test]
+    fn test_cidr() {
+        let cidr: Cidr = "10.0.0.0/8".parse().unwrap();
+        assert!(cidr.contains("10.1.2.3".parse().unwrap()));
+        assert!(!cidr.contains("11.0.0.1".parse().unwrap()));
+        assert!(cidr.contains("::ffff:10.1.2.3".parse().unwrap()));
+
+        let cidr: Cidr = "fd00::/8".parse().unwrap();
+        assert!(cidr.contains("fd12::1".parse().unwrap()));
+        assert!(!cidr.contains("fe80::1".parse().unwrap()));
+
+        let cidr: Cidr = "127.0.0.1".parse().unwrap();
+        assert!(cidr.contains("127.0.0.1".parse().unwrap()));
+        assert!(!cidr.contains("127.0.0.2".parse().unwrap()));
+
+        assert!("0.0.0.0/0"
+            .parse::<Cidr>()
+            .unwrap()
+            .contains("8.8.8.8".parse().unwrap()));
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("example.com".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn test_host_and_proto() {
+        let proxies = TrustedProxies::parse("10.0.0.0/8, 127.0.0.1").unwrap();
+        let h = headers(&[
+            ("Host", "internal:8000"),
+            ("X-Forwarded-Host", "nuts.example.com"),
+            ("X-Forwarded-Proto", "https"),
+        ]);
+
+        assert_eq!(
+            proxies.host(&h, ip("10.0.0.5")).as_deref(),
+            Some("nuts.example.com")
+        );
+        assert_eq!(proxies.proto(&h, ip("10.0.0.5")).as_deref(), Some("https"));
+        assert_eq!(
+            proxies.host(&h, ip("203.0.113.9")).as_deref(),
+            Some("internal:8000")
+        );
+        assert_eq!(proxies.proto(&h, ip("203.0.113.9")), None);
+        assert_eq!(proxies.host(&h, None).as_deref(), Some("internal:8000"));
+
+        let h = headers(&[
+            ("Host", "internal:8000"),
+            ("X-Forwarded-Host", "ignored.example.com"),
+            (
+                "Forwarded",
+                "for=192.0.2.60;proto=HTTPS;host=\"nuts.example.com\", for=10.0.0.2",
+            ),
+        ]);
+        assert_eq!(
+            proxies.host(&h, ip("127.0.0.1")).as_deref(),
+            Some("nuts.example.com")
+        );
+        assert_eq!(proxies.proto(&h, ip("127.0.0.1")).as_deref(), Some("https"));
+    }
+
+    #[test]
+    fn test_spoofed_host_and_proto() {
+        let proxies = TrustedProxies::parse("10.0.0.0/8").unwrap();
+
+        // The trusted proxy appends to the values sent by the client.
+        let h = headers(&[
+            ("Host", "internal:8000"),
+            ("X-Forwarded-Host", "evil.example.com, nuts.example.com"),
+            ("X-Forwarded-Proto", "http, https"),
+        ]);
+        assert_eq!(
+            proxies.host(&h, ip("10.0.0.5")).as_deref(),
+            Some("nuts.example.com")
+        );
+        assert_eq!(proxies.proto(&h, ip("10.0.0.5")).as_deref(), Some("https"));
+
+        let h = headers(&[
+            ("Host", "internal:8000"),
+            (
+                "Forwarded",
+                "for=192.0.2.1;host=evil.example.com;proto=http, \
+                 for=203.0.113.9;host=nuts.example.com;proto=https",
+            ),
+        ]);
+        assert_eq!(
+            proxies.host(&h, ip("10.0.0.5")).as_deref(),
+            Some("nuts.example.com")
+        );
+        assert_eq!(proxies.proto(&h, ip("10.0.0.5")).as_deref(), Some("https"));
+
+        // An element added by an untrusted hop is never used.
+        let h = headers(&[
+            ("Host", "internal:8000"),
+            (
+                "Forwarded",
+                "for=192.0.2.1;host=evil.example.com;proto=http, for=203.0.113.9",
+            ),
+        ]);
+        assert_eq!(
+            proxies.host(&h, ip("10.0.0.5")).as_deref(),
+            Some("internal:8000")
+        );
+        assert_eq!(proxies.proto(&h, ip("10.0.0.5")), None);
+    }
+
+    #[test]
+    fn test_client_ip() {
+        let proxies = TrustedProxies::parse("10.0.0.0/8").unwrap();
+        let h = headers(&[("X-Forwarded-For", "198.51.100.7, 203.0.113.1, 10.0.0.3")]);
+        assert_eq!(proxies.client_ip(&h, ip("10.0.0.1")), ip("203.0.113.1"));
+        assert_eq!(proxies.client_ip(&h, ip("192.0.2.1")), ip("192.0.2.1"));
+
+        let h = headers(&[(
+            "Forwarded",
+            "for=\"[2001:db8:cafe::17]:4711\", for=10.0.0.3:8080",
+        )]);
+        assert_eq!(
+            proxies.client_ip(&h, ip("10.0.0.1")),
+            ip("2001:db8:cafe::17")
+        );
+
+        let h = headers(&[("Forwarded", "for=unknown, for=10.0.0.3")]);
+        assert_eq!(proxies.client_ip(&h, ip("10.0.0.1")), ip("10.0.0.3"));
+    }
+}
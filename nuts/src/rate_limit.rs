@@ -0,0 +1,228 @@
+use failure::Error;
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::Status;
+use rocket::{Request, Response};
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How often buckets that are full again are dropped.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The number of requests allowed in a period, e.g. '60/m'. The period is one of 's', 'm' or
+/// 'h', or a number of seconds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Limit {
+    pub requests: u32,
+    pub period: Duration,
+}
+
+impl Limit {
+    /// The number of requests that is added back to a bucket per second.
+    fn rate(&self) -> f64 {
+        f64::from(self.requests) / self.period.as_secs_f64()
+    }
+}
+
+impl FromStr for Limit {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (requests, period) = match s.trim().split_once('/') {
+            Some(x) => x,
+            None => bail!("Invalid rate limit {}", s),
+        };
+
+        let requests: u32 = requests.trim().parse()?;
+        let period = match period.trim() {
+            "s" => Duration::from_secs(1),
+            "m" => Duration::from_secs(60),
+            "h" => Duration::from_secs(60 * 60),
+            x => Duration::from_secs(x.parse()?),
+        };
+        if requests == 0 || period.as_secs() == 0 {
+            bail!("Invalid rate limit {}", s);
+        }
+
+        Ok(Limit { requests, period })
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+#[derive(Debug, Default)]
+struct Buckets {
+    buckets: HashMap<(String, String), Bucket>,
+    swept: Option<Instant>,
+}
+
+/// A token bucket rate limiter with a limit per route. Buckets are kept per route and client,
+/// routes without a limit are not limited.
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    limits: HashMap<String, Limit>,
+    buckets: Mutex<Buckets>,
+}
+
+impl RateLimiter {
+    pub fn new(limits: HashMap<String, Limit>) -> Self {
+        RateLimiter {
+            limits,
+            buckets: Mutex::default(),
+        }
+    }
+
+    /// Takes a token from the bucket of a client for a route. Returns how long the client has to
+    /// wait for the next token when the bucket is empty.
+    pub fn check(&self, route: &str, client: &str, now: Instant) -> Result<(), Duration> {
+        let limit = match self.limits.get(route) {
+            Some(limit) => limit,
+            None => return Ok(()),
+        };
+
+        let mut state = self.buckets.lock().unwrap();
+        if state
+            .swept
+            .map_or(true, |x| elapsed(x, now) >= SWEEP_INTERVAL.as_secs_f64())
+        {
+            self.sweep(&mut state.buckets, now);
+            state.swept = Some(now);
+        }
+
+        let bucket = state
+            .buckets
+            .entry((route.to_string(), client.to_string()))
+            .or_insert(Bucket {
+                tokens: f64::from(limit.requests),
+                updated: now,
+            });
+        bucket.tokens = (bucket.tokens + elapsed(bucket.updated, now) * limit.rate())
+            .min(f64::from(limit.requests));
+        bucket.updated = now;
+
+        if bucket.tokens < 1.0 {
+            return Err(Duration::from_secs_f64(
+                (1.0 - bucket.tokens) / limit.rate(),
+            ));
+        }
+
+        bucket.tokens -= 1.0;
+        Ok(())
+    }
+
+    /// Drops the buckets that are full again, a client without a bucket gets a full one.
+    fn sweep(&self, buckets: &mut HashMap<(String, String), Bucket>, now: Instant) {
+        buckets.retain(|(route, _), bucket| {
+            self.limits.get(route).map_or(false, |x| {
+                bucket.tokens + elapsed(bucket.updated, now) * x.rate() < f64::from(x.requests)
+            })
+        });
+    }
+}
+
+fn elapsed(since: Instant, now: Instant) -> f64 {
+    now.saturating_duration_since(since).as_secs_f64()
+}
+
+/// The number of seconds a rate limited client has to wait, kept in the request-local cache.
+#[derive(Debug, Default)]
+pub struct RetryAfter(pub Option<u64>);
+
+/// RetryAfterHeader is a fairing that adds the 'Retry-After' header to responses of rate
+/// limited requests.
+pub struct RetryAfterHeader;
+
+impl Fairing for RetryAfterHeader {
+    fn info(&self) -> Info {
+        Info {
+            name: "Retry-After header",
+            kind: Kind::Response,
+        }
+    }
+
+    fn on_response(&self, request: &Request, response: &mut Response) {
+        if response.status() != Status::TooManyRequests {
+            return;
+        }
+
+        if let Some(seconds) = request.local_cache(RetryAfter::default).0 {
+            response.set_raw_header("Retry-After", seconds.to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_limit() {
+        assert_eq!(
+            "60/m".parse::<Limit>().unwrap(),
+            Limit {
+                requests: 60,
+                period: Duration::from_secs(60)
+            }
+        );
+        assert_eq!(
+            "5/30".parse::<Limit>().unwrap().period,
+            Duration::from_secs(30)
+        );
+        assert!("60".parse::<Limit>().is_err());
+        assert!("0/m".parse::<Limit>().is_err());
+        assert!("10/d".parse::<Limit>().is_err());
+    }
+
+    #[test]
+    fn test_check() {
+        let mut limits = HashMap::new();
+        limits.insert("update".to_string(), "2/10".parse().unwrap());
+        let limiter = RateLimiter::new(limits);
+        let now = Instant::now();
+
+        assert!(limiter.check("update", "ip:10.0.0.1", now).is_ok());
+        assert!(limiter.check("update", "ip:10.0.0.1", now).is_ok());
+        assert_eq!(
+            limiter.check("update", "ip:10.0.0.1", now),
+            Err(Duration::from_secs(5))
+        );
+
+        // Other clients and routes have their own buckets.
+        assert!(limiter.check("update", "ip:10.0.0.2", now).is_ok());
+        for _ in 0..10 {
+            assert!(limiter.check("download", "ip:10.0.0.1", now).is_ok());
+        }
+
+        // A token is added back every 5 seconds.
+        let later = now + Duration::from_secs(5);
+        assert!(limiter.check("update", "ip:10.0.0.1", later).is_ok());
+        assert!(limiter.check("update", "ip:10.0.0.1", later).is_err());
+    }
+
+    #[test]
+    fn test_sweep() {
+        let mut limits = HashMap::new();
+        limits.insert("update".to_string(), "2/10".parse().unwrap());
+        let limiter = RateLimiter::new(limits);
+        let now = Instant::now();
+
+        for i in 0..100 {
+            assert!(limiter.check("update", &format!("ip:{}", i), now).is_ok());
+        }
+        assert_eq!(limiter.buckets.lock().unwrap().buckets.len(), 100);
+
+        // Buckets are swept once a minute, by then the buckets of idle clients are full again.
+        let later = now + Duration::from_secs(30);
+        assert!(limiter.check("update", "ip:0", later).is_ok());
+        assert_eq!(limiter.buckets.lock().unwrap().buckets.len(), 100);
+
+        let later = now + SWEEP_INTERVAL;
+        assert!(limiter.check("update", "ip:1", later).is_ok());
+        assert_eq!(limiter.buckets.lock().unwrap().buckets.len(), 1);
+    }
+}
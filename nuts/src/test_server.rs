@@ -0,0 +1,57 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::thread;
+
+/// A request received by the test server, its header lines are lowercased.
+pub struct Request {
+    pub headers: Vec<String>,
+    pub body: String,
+}
+
+/// Starts a server on a free local port that answers every request with the status and body
+/// returned by `handler`. Returns the url of the server, e.g. 'http://127.0.0.1:4321'.
+pub fn serve<F>(mut handler: F) -> String
+where
+    F: FnMut(Request) -> (u16, String) + Send + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut headers = vec![];
+            let mut length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let line = line.trim().to_lowercase();
+                if line.is_empty() {
+                    break;
+                }
+                if let Some(x) = line.strip_prefix("content-length:") {
+                    length = x.trim().parse().unwrap();
+                }
+                headers.push(line);
+            }
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+
+            let (status, body) = handler(Request {
+                headers,
+                body: String::from_utf8(body).unwrap(),
+            });
+            write!(
+                stream,
+                "HTTP/1.1 {} Status\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            )
+            .unwrap();
+        }
+    });
+
+    url
+}
@@ -0,0 +1,520 @@
+use crate::audit::{self, AuditLog};
+use crate::clock;
+use crate::events::ReleaseEvent;
+use crate::logging;
+use failure::Error;
+use handlebars::Handlebars;
+use hmac::{Hmac, Mac};
+use reqwest::header::CONTENT_TYPE;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::Sha256;
+use std::fmt::Write;
+use std::fs;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+/// How often a failed delivery is retried before it is given up.
+pub const DEFAULT_RETRIES: u32 = 5;
+
+/// How long to wait before the first retry, the wait doubles with every retry.
+pub const DEFAULT_BACKOFF: Duration = Duration::from_secs(1);
+
+/// How long a request to a webhook may take.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Header with the HMAC-SHA256 of the body, hex encoded and prefixed with 'sha256='.
+pub const SIGNATURE_HEADER: &str = "X-Nuts-Signature";
+
+/// Header with the kind of event, e.g. 'release-added'.
+pub const EVENT_HEADER: &str = "X-Nuts-Event";
+
+/// The events a webhook can be notified of.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Kind {
+    ReleaseAdded,
+    ReleaseYanked,
+    RolloutChanged,
+}
+
+impl Kind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Kind::ReleaseAdded => "release-added",
+            Kind::ReleaseYanked => "release-yanked",
+            Kind::RolloutChanged => "rollout-changed",
+        }
+    }
+}
+
+/// An endpoint that is notified of changes to the releases.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Webhook {
+    pub url: String,
+
+    /// The events the webhook is notified of, all events when empty.
+    #[serde(default)]
+    pub events: Vec<Kind>,
+
+    /// Secret the body is signed with, see `SIGNATURE_HEADER`.
+    pub secret: Option<String>,
+
+    /// The body to send, strings in it are handlebars templates that are rendered with the
+    /// notification, e.g. `{"text": "Released {{version}}"}`. The notification is sent as is
+    /// when not set.
+    pub template: Option<Value>,
+}
+
+impl Webhook {
+    /// Reads webhooks from a JSON file that contains a list of webhooks.
+    pub fn load(path: &str) -> Result<Vec<Self>, Error> {
+        let webhooks: Vec<Webhook> = serde_json::from_str(&fs::read_to_string(path)?)?;
+        for webhook in &webhooks {
+            webhook.payload(&Notification::new(Kind::ReleaseAdded, "1.0.0"))?;
+        }
+
+        Ok(webhooks)
+    }
+
+    /// Returns the body to send for a notification.
+    pub fn payload(&self, notification: &Notification) -> Result<String, Error> {
+        let context = serde_json::to_value(notification)?;
+        let body = match &self.template {
+            Some(template) => {
+                let mut registry = Handlebars::new();
+                registry.register_escape_fn(handlebars::no_escape);
+                render(&registry, template, &context)?
+            }
+            None => context,
+        };
+
+        Ok(serde_json::to_string(&body)?)
+    }
+}
+
+/// Renders the strings in a template, keys are left as they are.
+fn render(registry: &Handlebars, template: &Value, context: &Value) -> Result<Value, Error> {
+    Ok(match template {
+        Value::String(x) => Value::String(registry.render_template(x, context)?),
+        Value::Array(xs) => Value::Array(
+            xs.iter()
+                .map(|x| render(registry, x, context))
+                .collect::<Result<_, _>>()?,
+        ),
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .map(|(k, v)| Ok((k.clone(), render(registry, v, context)?)))
+                .collect::<Result<_, Error>>()?,
+        ),
+        x => x.clone(),
+    })
+}
+
+/// Returns the hex encoded HMAC-SHA256 of a body.
+pub fn sign(secret: &str, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(body.as_bytes());
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .fold(String::new(), |mut out, x| {
+            let _ = write!(out, "{:02x}", x);
+            out
+        })
+}
+
+/// The data a webhook is notified with, which is also the context of its template.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Notification {
+    pub event: Kind,
+
+    /// Unix timestamp of the event.
+    pub timestamp: i64,
+
+    /// The Github repository the release belongs to.
+    pub app: String,
+    pub version: String,
+
+    /// The filenames of the assets that were added.
+    pub assets: Vec<String>,
+    pub rollout: Option<u8>,
+}
+
+impl Notification {
+    pub fn new(event: Kind, version: &str) -> Self {
+        Notification {
+            event,
+            timestamp: clock::now(),
+            app: String::new(),
+            version: version.to_string(),
+            assets: vec![],
+            rollout: None,
+        }
+    }
+
+    pub fn rollout(mut self, rollout: u8) -> Self {
+        self.rollout = Some(rollout);
+        self
+    }
+
+    /// Returns the notifications for the changes of a refresh of the release index, one per
+    /// version that was added or whose rollout changed.
+    pub fn from_events(events: &[ReleaseEvent]) -> Vec<Self> {
+        let mut out: Vec<Notification> = vec![];
+        for event in events {
+            let (kind, asset) = match event {
+                ReleaseEvent::Added { asset } => (Kind::ReleaseAdded, asset),
+                ReleaseEvent::Changed { old, new } if old.rollout != new.rollout => {
+                    (Kind::RolloutChanged, new)
+                }
+                _ => continue,
+            };
+
+            let index = match out
+                .iter()
+                .position(|x| x.event == kind && x.version == asset.version)
+            {
+                Some(index) => index,
+                None => {
+                    let mut notification = Notification::new(kind, &asset.version);
+                    if kind == Kind::RolloutChanged {
+                        notification = notification.rollout(asset.rollout.unwrap_or(100));
+                    }
+                    out.push(notification);
+                    out.len() - 1
+                }
+            };
+            if kind == Kind::ReleaseAdded {
+                out[index].assets.push(asset.filename.clone());
+            }
+        }
+
+        out
+    }
+}
+
+/// Delivers notifications to the webhooks in the background. Every webhook has a thread of its
+/// own, so a slow receiver does not hold up the others. Clones share the threads.
+#[derive(Clone, Default)]
+pub struct Webhooks {
+    app: String,
+    targets: Vec<(Vec<Kind>, mpsc::Sender<Notification>)>,
+}
+
+impl Webhooks {
+    /// Starts a thread per webhook, a failed delivery is retried `retries` times with an
+    /// exponential backoff. Deliveries are recorded in the audit log.
+    pub fn start(
+        app: &str,
+        webhooks: Vec<Webhook>,
+        retries: u32,
+        backoff: Duration,
+        audit: AuditLog,
+    ) -> Self {
+        let mut targets = vec![];
+        for webhook in webhooks {
+            let (sender, receiver) = mpsc::channel::<Notification>();
+            targets.push((webhook.events.clone(), sender));
+            let audit = audit.clone();
+
+            thread::Builder::new()
+                .name("webhook".to_string())
+                .spawn(move || run(&webhook, receiver, retries, backoff, &audit))
+                .expect("could not start webhook");
+        }
+
+        Webhooks {
+            app: app.to_string(),
+            targets,
+        }
+    }
+
+    /// Queues a notification for the webhooks that want to be notified of its event.
+    pub fn notify(&self, mut notification: Notification) {
+        notification.app = self.app.clone();
+        for (events, sender) in &self.targets {
+            if events.is_empty() || events.contains(&notification.event) {
+                let _ = sender.send(notification.clone());
+            }
+        }
+    }
+}
+
+/// Delivers the notifications for a webhook one by one, until the sender is dropped.
+fn run(
+    webhook: &Webhook,
+    receiver: mpsc::Receiver<Notification>,
+    retries: u32,
+    backoff: Duration,
+    audit: &AuditLog,
+) {
+    let client = reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .build()
+        .expect("could not create webhook client");
+    let url = logging::redact_url(&webhook.url);
+    for notification in receiver {
+        let event = notification.event.as_str();
+        let version = notification.version.as_str();
+        let (action, attempts) = match deliver(&client, webhook, &notification, retries, backoff) {
+            Ok(attempts) => {
+                info!(url, event, version, attempts; "delivered webhook");
+                ("webhook.delivered", attempts)
+            }
+            Err(e) => {
+                error!(url, event, version, error:% = e; "could not deliver webhook");
+                ("webhook.failed", retries + 1)
+            }
+        };
+        audit.record(
+            audit::Entry::new(action)
+                .actor(Some(audit::SYSTEM_ACTOR))
+                .target(&url)
+                .detail(&format!("{} {}, {} attempts", event, version, attempts)),
+        );
+    }
+}
+
+/// Sends a notification until it is accepted, returns the number of attempts it took.
+fn deliver(
+    client: &reqwest::Client,
+    webhook: &Webhook,
+    notification: &Notification,
+    retries: u32,
+    backoff: Duration,
+) -> Result<u32, Error> {
+    let body = webhook.payload(notification)?;
+
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        match send(client, webhook, notification.event, &body) {
+            Ok(()) => return Ok(attempts),
+            Err(e) if attempts > retries => return Err(e),
+            Err(e) => {
+                let wait = backoff * 2u32.saturating_pow(attempts - 1);
+                warn!(
+                    url = logging::redact_url(&webhook.url),
+                    attempts,
+                    wait_ms = wait.as_millis() as u64,
+                    error:% = e;
+                    "webhook delivery failed, retrying"
+                );
+                thread::sleep(wait);
+            }
+        }
+    }
+}
+
+fn send(client: &reqwest::Client, webhook: &Webhook, event: Kind, body: &str) -> Result<(), Error> {
+    let mut request = client
+        .post(&webhook.url)
+        .header(CONTENT_TYPE, "application/json")
+        .header(EVENT_HEADER, event.as_str());
+    if let Some(secret) = &webhook.secret {
+        request = request.header(SIGNATURE_HEADER, format!("sha256={}", sign(secret, body)));
+    }
+
+    request.body(body.to_string()).send()?.error_for_status()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::events::Asset;
+    use crate::test_server::{self, Request};
+    use std::time::{Duration, Instant};
+
+    /// Starts a stand-in receiver that answers requests with the given statuses in turn, and
+    /// 200 once they run out.
+    fn receive(statuses: Vec<u16>) -> (String, mpsc::Receiver<Request>) {
+        let (sender, receiver) = mpsc::channel();
+        let mut statuses = statuses.into_iter();
+        let url = test_server::serve(move |request| {
+            let _ = sender.send(request);
+            (statuses.next().unwrap_or(200), String::new())
+        });
+
+        (format!("{}/hook", url), receiver)
+    }
+
+    fn asset(filename: &str, rollout: Option<u8>) -> Asset {
+        Asset {
+            filename: filename.to_string(),
+            version: filename.split('-').nth(1).unwrap().to_string(),
+            platform: "osx".to_string(),
+            arch: None,
+            channel: None,
+            notes: None,
+            rollout,
+            critical: false,
+            size: None,
+            checksum: None,
+            published_at: None,
+        }
+    }
+
+    #[test]
+    fn test_sign() {
+        assert_eq!(
+            sign("key", "The quick brown fox jumps over the lazy dog"),
+            "f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+        );
+    }
+
+    #[test]
+    fn test_payload() {
+        let webhook: Webhook = serde_json::from_str(
+            r#"{
+                "url": "http://localhost/hook",
+                "template": {"text": "Released {{app}} {{version}} \"{{assets.[0]}}\"", "count": 1}
+            }"#,
+        )
+        .unwrap();
+        let mut notification = Notification::new(Kind::ReleaseAdded, "1.2.0");
+        notification.app = "tacitic/app".to_string();
+        notification.assets = vec!["app-1.2.0-mac.zip".to_string()];
+
+        let body: Value = serde_json::from_str(&webhook.payload(&notification).unwrap()).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "text": "Released tacitic/app 1.2.0 \"app-1.2.0-mac.zip\"",
+                "count": 1
+            })
+        );
+
+        let webhook = Webhook {
+            template: None,
+            ..webhook
+        };
+        let body: Value = serde_json::from_str(&webhook.payload(&notification).unwrap()).unwrap();
+        assert_eq!(body["event"], "release-added");
+        assert_eq!(body["version"], "1.2.0");
+    }
+
+    #[test]
+    fn test_from_events() {
+        let events = vec![
+            ReleaseEvent::Added {
+                asset: asset("app-1.3.0-mac.zip", None),
+            },
+            ReleaseEvent::Added {
+                asset: asset("app-1.3.0-win.exe", None),
+            },
+            ReleaseEvent::Changed {
+                old: Box::new(asset("app-1.2.0-mac.zip", Some(10))),
+                new: asset("app-1.2.0-mac.zip", Some(50)),
+            },
+            ReleaseEvent::Changed {
+                old: Box::new(asset("app-1.1.0-mac.zip", None)),
+                new: Asset {
+                    critical: true,
+                    ..asset("app-1.1.0-mac.zip", None)
+                },
+            },
+            ReleaseEvent::Removed {
+                asset: asset("app-1.0.0-mac.zip", None),
+            },
+        ];
+
+        let out = Notification::from_events(&events);
+        assert_eq!(out.len(), 2);
+        assert_eq!(out[0].event, Kind::ReleaseAdded);
+        assert_eq!(
+            out[0].assets,
+            vec!["app-1.3.0-mac.zip", "app-1.3.0-win.exe"]
+        );
+        assert_eq!(out[1].event, Kind::RolloutChanged);
+        assert_eq!(out[1].version, "1.2.0");
+        assert_eq!(out[1].rollout, Some(50));
+    }
+
+    #[test]
+    fn test_deliver() {
+        let (url, received) = receive(vec![500, 503]);
+        let webhook = Webhook {
+            url,
+            events: vec![Kind::ReleaseYanked],
+            secret: Some("s3cret".to_string()),
+            template: None,
+        };
+        let webhooks = Webhooks::start(
+            "tacitic/app",
+            vec![webhook],
+            2,
+            Duration::from_millis(10),
+            AuditLog::disabled(),
+        );
+
+        // Only the yank is delivered, after two failed attempts.
+        webhooks.notify(Notification::new(Kind::ReleaseAdded, "1.3.0"));
+        webhooks.notify(Notification::new(Kind::ReleaseYanked, "1.2.0"));
+        let attempts: Vec<Request> = (0..3)
+            .map(|_| received.recv_timeout(Duration::from_secs(5)).unwrap())
+            .collect();
+
+        let last = &attempts[2];
+        assert_eq!(attempts[0].body, last.body);
+        let body: Value = serde_json::from_str(&last.body).unwrap();
+        assert_eq!(body["event"], "release-yanked");
+        assert_eq!(body["app"], "tacitic/app");
+        assert!(last
+            .headers
+            .contains(&"x-nuts-event: release-yanked".to_string()));
+        assert!(last.headers.contains(&format!(
+            "x-nuts-signature: sha256={}",
+            sign("s3cret", &last.body)
+        )));
+    }
+
+    #[test]
+    fn test_give_up() {
+        let dir = tempfile::tempdir().unwrap();
+        let audit =
+            AuditLog::open(dir.path().join("audit.jsonl"), audit::DEFAULT_MAX_SIZE).unwrap();
+        let (url, received) = receive(vec![500, 500]);
+        let webhook = Webhook {
+            url,
+            events: vec![],
+            secret: None,
+            template: None,
+        };
+        let webhooks = Webhooks::start(
+            "tacitic/app",
+            vec![webhook],
+            1,
+            Duration::from_millis(10),
+            audit.clone(),
+        );
+
+        webhooks.notify(Notification::new(Kind::ReleaseYanked, "1.2.0"));
+        webhooks.notify(Notification::new(Kind::ReleaseYanked, "1.3.0"));
+
+        // The first notification is given up after a retry, the next one is delivered.
+        let bodies: Vec<String> = (0..3)
+            .map(|_| received.recv_timeout(Duration::from_secs(5)).unwrap().body)
+            .collect();
+        assert!(bodies[1].contains("1.2.0"));
+        assert!(bodies[2].contains("1.3.0"));
+
+        let started = Instant::now();
+        let query = audit::Query::default();
+        while audit.query(&query).unwrap().len() < 2 {
+            assert!(started.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(10));
+        }
+        let entries = audit.query(&query).unwrap();
+        assert_eq!(entries[1].action, "webhook.failed");
+        assert_eq!(
+            entries[1].detail.as_deref(),
+            Some("release-yanked 1.2.0, 2 attempts")
+        );
+        assert_eq!(entries[0].action, "webhook.delivered");
+        assert_eq!(entries[0].actor.as_deref(), Some("nuts"));
+        assert!(entries[0].target.as_deref().unwrap().ends_with("/hook"));
+    }
+}
@@ -1,5 +1,6 @@
 use reqwest::{Url};
 
+#[derive(Clone)]
 pub struct Config {
     pub base_url: Url,
     pub auth: Option<String>,
@@ -0,0 +1,30 @@
+use crate::error::ErrorKind;
+use crate::{util, Config};
+use failure::Error;
+use reqwest::Method;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RateLimit {
+    pub rate: Rate,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Rate {
+    pub limit: u32,
+    pub remaining: u32,
+    pub reset: u64,
+}
+
+/// Returns the rate limit status of the configured credentials, this request does not count
+/// against the rate limit itself.
+pub fn get_rate_limit(cfg: &Config) -> Result<RateLimit, Error> {
+    let b = util::get_request_builder(cfg, Method::GET, "/rate_limit".to_string());
+
+    let mut x = b.send()?;
+    if !x.status().is_success() {
+        return Err(ErrorKind::UnexpectedStatus(x.status().as_u16()).into());
+    }
+
+    Ok(x.json()?)
+}
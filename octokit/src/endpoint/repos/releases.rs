@@ -1,7 +1,8 @@
 
+use crate::error::ErrorKind;
 use crate::{util, Config};
 use failure::Error;
-use reqwest::{Client, Method, RequestBuilder, Response};
+use reqwest::{Method, Response};
 use serde::{Deserialize, Serialize};
 
 
@@ -17,10 +18,11 @@ pub struct Release {
     pub tag_name: String,
     pub target_commitish: String,
     pub name: String,
+    pub body: Option<String>,
     pub draft: bool,
     pub prerelease: bool,
     pub created_at: String,
-    pub published_at: String,
+    pub published_at: Option<String>,
     pub assets: Vec<Asset>,
 }
 
@@ -61,26 +63,29 @@ pub struct Asset {
     pub created_at: String,
     pub updated_at: String,
     pub browser_download_url: String,
+    #[serde(default)]
+    pub digest: Option<String>,
 }
 
 pub fn list_releases(cfg: &Config, repo: &str) -> Result<Vec<Release>, Error> {
-    let b = util::get_request_builder(&cfg, Method::GET, format!("/repos/{}/releases", repo));
+    let b = util::get_request_builder(cfg, Method::GET, format!("/repos/{}/releases", repo));
     let responses = util::paginate(&b, 1, 30)?;
     let json: Result<Vec<Vec<Release>>, _> = responses.into_iter().map(map_release).collect();
-    match json {
-        Ok(x) => Ok(x.into_iter().flatten().collect()),
-        Err(e) => Err(Error::from(e)),
-    }
+    Ok(json?.into_iter().flatten().collect())
 }
 
 pub fn download_asset(cfg: &Config, repo: &str, asset_id: u32) -> Result<Response, Error> {
     let b = util::get_request_builder(
-        &cfg,
+        cfg,
         Method::GET,
         format!("/repos/{}/releases/assets/{}", repo, asset_id),
     );
 
     let x = b.header("Accept", "application/octet-stream").send()?;
+    if !x.status().is_success() {
+        return Err(ErrorKind::UnexpectedStatus(x.status().as_u16()).into());
+    }
+
     Ok(x)
 }
 
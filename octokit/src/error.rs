@@ -4,6 +4,9 @@ use std::fmt;
 /// The specific kind of error that can occur.
 #[derive(Clone, Eq, PartialEq, Debug, Fail)]
 pub enum ErrorKind {
+    /// Github responded with an unexpected status code
+    #[fail(display = "Unexpected status code: {}", _0)]
+    UnexpectedStatus(u16),
     /// A serialization / deserialization error
     #[fail(display = "Serialization error: {}", _0)]
     SerdeError(String),
@@ -2,9 +2,7 @@
 ///
 /// ## Usage
 /// ```
-/// fn main() {
-///     let cfg = octokit::Config::new_authenticated("secret_access_token");
-/// }
+/// let cfg = octokit::Config::new_authenticated("secret_access_token");
 /// ```
 #[macro_use]
 extern crate nom;
@@ -15,6 +13,7 @@ extern crate nom;
 
 mod config;
 pub mod endpoint;
+#[allow(dead_code)]
 pub(crate) mod error;
 pub(crate) mod util;
 
@@ -3,4 +3,4 @@ mod parser;
 mod request;
 
 pub use pagination::paginate;
-pub use request::{get_client, get_request_builder};
+pub use request::get_request_builder;
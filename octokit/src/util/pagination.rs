@@ -1,4 +1,6 @@
-use crate::{util};
+use crate::error::ErrorKind;
+use crate::util;
+use failure::Error;
 use reqwest::header::HeaderMap;
 use reqwest::{RequestBuilder, Response, Url};
 use std::str::FromStr;
@@ -8,7 +10,7 @@ pub fn paginate(
     req: &RequestBuilder,
     from: u32,
     per_page: u32,
-) -> Result<Vec<Response>, reqwest::Error> {
+) -> Result<Vec<Response>, Error> {
     let mut pager = Some(from);
     let mut out = vec![];
 
@@ -20,11 +22,9 @@ pub fn paginate(
             .send()?;
 
         if !res.status().is_success() {
-            pager = None;
-            panic!("non 200 status code returned {:?}", res);
+            return Err(ErrorKind::UnexpectedStatus(res.status().as_u16()).into());
         }
 
-        let _lh = LinkHeaders::new(res.headers());
         match LinkHeaders::new(res.headers()) {
             Some(lh) => {
                 if lh.should_next(page) {
@@ -39,7 +39,7 @@ pub fn paginate(
         out.push(res);
     }
 
-    return Ok(out);
+    Ok(out)
 }
 
 #[derive(Debug, PartialEq)]
@@ -79,12 +79,6 @@ pub(crate) struct LinkHeader {
     pub rel: LinkHeaderType,
 }
 
-impl LinkHeader {
-    fn new(s: &str) -> Result<Self, String> {
-        util::parser::link_header(s)
-    }
-}
-
 #[derive(Debug)]
 struct LinkHeaders {
     next: Option<LinkHeader>,
@@ -104,8 +98,8 @@ impl LinkHeaders {
                     last: None,
                 };
 
-                for link in raw_link_header.to_str().unwrap().split(",") {
-                    let lh = util::parser::link_header(&link).unwrap();
+                for link in raw_link_header.to_str().unwrap().split(',') {
+                    let lh = util::parser::link_header(link).unwrap();
                     match lh.rel {
                         LinkHeaderType::Next => out.next = Some(lh),
                         LinkHeaderType::Prev => out.prev = Some(lh),
@@ -123,9 +117,9 @@ impl LinkHeaders {
 
     fn should_next(&self, page: u32) -> bool {
         if let Some(next) = &self.next {
-            return next.page != page;
+            next.page != page
         } else {
-            return false;
+            false
         }
     }
 
@@ -133,6 +127,6 @@ impl LinkHeaders {
         if let Some(n) = &self.next {
             return n.page;
         }
-        return current;
+        current
     }
 }
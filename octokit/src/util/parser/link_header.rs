@@ -1,3 +1,6 @@
+// nom 5 deprecated the `ws!` combinator, the parsers below still rely on it.
+#![allow(deprecated)]
+
 use crate::util::pagination::LinkHeader;
 use nom::bytes::complete::tag;
 use nom::character::complete::alphanumeric1;
@@ -39,7 +42,7 @@ named_attr!(#[doc="Parser a link-header"],
             page: get_param(&url, "page").unwrap(),
             per_page: get_param(&url, "per_page").unwrap(),
             rel: get_arg(args, "rel").unwrap(),
-            url: url,
+            url,
         }))
     ));
 
@@ -49,7 +52,7 @@ fn get_param<T: FromStr>(url: &Url, key: &str) -> Option<T> {
             return val.parse().ok();
         }
     }
-    return None;
+    None
 }
 
 fn get_arg<T: FromStr>(args: Vec<(&str, &str)>, key: &str) -> Option<T> {
@@ -58,7 +61,7 @@ fn get_arg<T: FromStr>(args: Vec<(&str, &str)>, key: &str) -> Option<T> {
             return val.parse().ok();
         }
     }
-    return None;
+    None
 }
 
 #[cfg(test)]
@@ -12,7 +12,7 @@ pub fn get_request_builder(cfg: &Config, method: Method, path: String) -> Reques
         request_builder = request_builder.bearer_auth(a);
     }
 
-    return request_builder;
+    request_builder
 }
 
 // TODO: docs
@@ -37,7 +37,7 @@ pub fn sign_url(secret: &str, url: &str, exp: time::SystemTime) -> Result<String
         .clear()
         .extend_pairs(query_params);
 
-    let signature = get_signature(&url_for_signing, &secret)?;
+    let signature = get_signature(&url_for_signing, secret)?;
 
     let mut signed_url = url.clone();
     signed_url
@@ -88,7 +88,7 @@ pub fn validate(secret: &str, url: &str) -> Result<(), Error> {
         .clear()
         .extend_pairs(query_params);
 
-    let signature = get_signature(&url_for_signing, &secret)?;
+    let signature = get_signature(&url_for_signing, secret)?;
 
     if let Some(s) = url_signature {
         if s != signature {